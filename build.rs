@@ -11,6 +11,8 @@ fn compile_static_resources() {
     let mut resource_path = env::current_dir().unwrap();
     resource_path.push("src/resources");
 
+    println!("cargo:rerun-if-changed={}", resource_path.display());
+
     let paths = fs::read_dir(resource_path).unwrap();
 
     let head = "
@@ -20,13 +22,13 @@ use std::collections::HashMap;
 
 
 pub struct Resource {
-    pub r: HashMap<&'static str, &'static str>
+    pub r: HashMap<&'static str, &'static [u8]>
 }
 
 
 impl Resource {
     pub fn new() -> Resource {
-        let mut res: HashMap<&'static str, &'static str> = HashMap::new();
+        let mut res: HashMap<&'static str, &'static [u8]> = HashMap::new();
 "
     .to_string();
 
@@ -46,16 +48,14 @@ impl Resource {
         if path.file_type().unwrap().is_dir() {
             continue;
         }
-        let mut file = File::open(path.path().as_os_str()).unwrap();
-        let mut buff = String::new();
-        file.read_to_string(&mut buff).unwrap();
-
-        let f_body = buff.replace("\"", "\\\"");
+        let f_path = path.path().to_str().unwrap().to_string();
         let f_name = path.file_name().into_string().unwrap();
 
+        // include_bytes! keeps binary files such as icons intact and
+        // doesn't need any escaping.
         body.push_str(&format!(
-            "        res.insert(\"/resource/{}\", \"{}\");\n",
-            f_name, f_body
+            "        res.insert({:?}, &include_bytes!({:?})[..]);\n",
+            f_name, f_path
         ));
     }
 
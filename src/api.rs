@@ -0,0 +1,85 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use percent_encoding::utf8_percent_encode;
+
+use directory::FileMeta;
+use format;
+use locale;
+use theme;
+
+/// Where the versioned API lives. Unlike `/json`, the shape of what it
+/// answers with is described in `openapi.json` and fields only ever get
+/// added to it.
+pub const PREFIX: &'static str = "/api/v1";
+
+const OPENAPI: &'static str = include_str!("openapi.json");
+
+/// A shared file.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct File {
+    pub name: String,
+    pub size: u64,
+    /// UTC time in RFC 3339 format.
+    pub modified: String,
+    /// Seconds since the unix epoch.
+    pub modified_unix: u64,
+    pub content_type: String,
+    /// Where the contents of the file are downloaded from.
+    pub content_url: String,
+}
+
+impl File {
+    pub fn new(meta: &FileMeta) -> File {
+        File {
+            name: meta.name.clone(),
+            size: meta.size,
+            modified: locale::rfc3339(meta.modified_raw, true),
+            modified_unix: meta.modified_raw,
+            content_type: theme::content_type(&meta.name).to_string(),
+            content_url: format!(
+                "{}/files/{}/content",
+                PREFIX,
+                utf8_percent_encode(&meta.name, format::PATH_SEGMENT)
+            ),
+        }
+    }
+}
+
+/// What the server is and what it allows.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Info {
+    pub version: String,
+    pub api_version: u32,
+    pub upload: bool,
+    pub delete: bool,
+    /// Uploads replace the file of the same name.
+    pub overwrite: bool,
+    /// Clients only see the files they uploaded themselves.
+    pub dropbox: bool,
+    /// Deleted files can be restored from the trash.
+    pub trash: bool,
+    /// Uploads replacing a file keep the older versions.
+    pub versions: bool,
+}
+
+/// The OpenAPI description of `/api/v1`.
+pub fn openapi(version: &str) -> String {
+    OPENAPI.replace("{{version}}", version)
+}
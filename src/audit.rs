@@ -0,0 +1,250 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use chrono::naive::date::NaiveDate;
+use chrono::naive::datetime::NaiveDateTime;
+use getopts::Options;
+use rustc_serialize::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use format;
+use shutdown;
+
+pub const UPLOAD: &'static str = "upload";
+pub const DOWNLOAD: &'static str = "download";
+pub const DELETE: &'static str = "delete";
+/// A file moved back out of the trash or set back to an earlier version.
+pub const RESTORE: &'static str = "restore";
+/// A deleted file removed from the trash for good.
+pub const PURGE: &'static str = "purge";
+
+/// One line of the audit log.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Event {
+    /// One of "upload", "download", "delete", "restore" or "purge".
+    pub event: String,
+    /// The name the client uploaded the file as.
+    pub source_name: Option<String>,
+    /// The name of the file in the served directory.
+    pub saved_name: String,
+    pub size: u64,
+    /// Hex encoded sha-256 digest, known for uploads.
+    pub sha256: Option<String>,
+    pub remote_addr: String,
+    /// User name the client authenticated with, if any. Otherwise
+    /// `remote_addr` is all that's known about who it was.
+    pub user: Option<String>,
+    /// UTC time in RFC 3339 format.
+    pub time: String,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+impl Event {
+    pub fn new(
+        event: &str,
+        name: &str,
+        size: u64,
+        remote_addr: &str,
+        user: Option<String>,
+    ) -> Event {
+        let timestamp = now();
+        Event {
+            event: event.to_string(),
+            source_name: None,
+            saved_name: name.to_string(),
+            size: size,
+            sha256: None,
+            remote_addr: remote_addr.to_string(),
+            user: user,
+            time: NaiveDateTime::from_timestamp(timestamp as i64, 0)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            timestamp: timestamp,
+        }
+    }
+}
+
+/// Append-only log of uploads, downloads, deletions, restores and
+/// purges, one JSON object per line.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, event: &Event) -> io::Result<()> {
+        // A single write per line keeps entries from concurrent requests
+        // from interleaving.
+        let line = format!("{}\n", json::encode(event).unwrap());
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+/// Reads the events in the log at `path`. Lines that don't parse, such
+/// as one cut short by a crash, are skipped.
+pub fn read(path: &Path) -> io::Result<Vec<Event>> {
+    let file = File::open(path)?;
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(event) = json::decode::<Event>(&line?) {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+/// Which events `na audit` prints.
+pub struct Query {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub user: Option<String>,
+    pub file: Option<String>,
+    pub event: Option<String>,
+}
+
+impl Query {
+    /// A file matches either the name it was uploaded as or the one it
+    /// was saved under.
+    pub fn matches(&self, e: &Event) -> bool {
+        self.since.map_or(true, |t| e.timestamp >= t)
+            && self.until.map_or(true, |t| e.timestamp < t)
+            && self
+                .user
+                .as_ref()
+                .map_or(true, |u| e.user.as_ref() == Some(u))
+            && self.event.as_ref().map_or(true, |k| &e.event == k)
+            && self.file.as_ref().map_or(true, |f| {
+                &e.saved_name == f || e.source_name.as_ref() == Some(f)
+            })
+    }
+}
+
+fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+/// Parses a point in time given as a date ("2024-01-05", midnight UTC),
+/// a UTC date and time ("2024-01-05T10:30:00") or a duration meaning
+/// that long ago ("2h").
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let time = s.trim_end_matches('Z');
+    if let Ok(dt) = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(dt.timestamp() as u64);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(d.and_hms(0, 0, 0).timestamp() as u64);
+    }
+    match shutdown::parse_duration(s) {
+        Ok(ago) => Ok(now().saturating_sub(ago.as_secs())),
+        Err(_) => Err(format!(
+            "Invalid time \"{}\", expected a date like 2024-01-05, \
+             2024-01-05T10:30:00 or a duration like 2h",
+            s
+        )),
+    }
+}
+
+/// Entry point of `na audit`.
+pub fn run(program: &str, args: &[String]) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message and exit");
+    opts.optopt(
+        "",
+        "since",
+        "only events at or after this time, a date, a UTC date and time \
+         or a duration such as 2h meaning that long ago",
+        "TIME",
+    );
+    opts.optopt("", "until", "only events before this time", "TIME");
+    opts.optopt("", "user", "only events by this user", "NAME");
+    opts.optopt("", "file", "only events for this file", "NAME");
+    opts.optopt(
+        "",
+        "event",
+        "only events of this kind: upload, download, delete, restore or purge",
+        "KIND",
+    );
+    opts.optflag("", "json", "print the matching events as JSON lines");
+
+    let options = opts.parse(args).map_err(|e| e.to_string())?;
+    let brief = format!("Usage: {} audit [OPTIONS] LOG", program);
+    if options.opt_present("h") {
+        println!("{}", opts.usage(&brief));
+        return Ok(());
+    }
+    if options.free.len() != 1 {
+        return Err(opts.usage(&brief));
+    }
+    let query = Query {
+        since: match options.opt_str("since") {
+            Some(s) => Some(parse_time(&s)?),
+            None => None,
+        },
+        until: match options.opt_str("until") {
+            Some(s) => Some(parse_time(&s)?),
+            None => None,
+        },
+        user: options.opt_str("user"),
+        file: options.opt_str("file"),
+        event: options.opt_str("event"),
+    };
+    let path = &options.free[0];
+    let events = match read(Path::new(path)) {
+        Ok(e) => e,
+        Err(e) => return Err(format!("Unable to read audit log {}, {}", path, e)),
+    };
+
+    for e in events.iter().filter(|e| query.matches(e)) {
+        if options.opt_present("json") {
+            println!("{}", json::encode(e).unwrap());
+            continue;
+        }
+        let name = match e.source_name {
+            Some(ref s) if s != &e.saved_name => format!("{} (uploaded as {})", e.saved_name, s),
+            _ => e.saved_name.clone(),
+        };
+        println!(
+            "{}  {:<8}  {}  {}  {}  {}",
+            e.time,
+            e.event,
+            e.user.as_ref().map_or("-", |u| u.as_str()),
+            e.remote_addr,
+            name,
+            format::size(e.size)
+        );
+    }
+    Ok(())
+}
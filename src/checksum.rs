@@ -0,0 +1,231 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use md5::Md5;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::hex::FromHex;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Digest algorithms that uploads can be checked against.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "md5" => Some(Algorithm::Md5),
+            "sha-256" | "sha256" => Some(Algorithm::Sha256),
+            "sha-512" | "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha256 => "sha-256",
+            Algorithm::Sha512 => "sha-512",
+        }
+    }
+}
+
+/// A digest the client says an upload should have.
+pub struct Expected {
+    pub algorithm: Algorithm,
+    pub digest: Vec<u8>,
+}
+
+/// Digests computed over an upload. Only sha-256 is always there.
+pub struct Digests {
+    pub sha256: Vec<u8>,
+    pub sha512: Option<Vec<u8>>,
+    pub md5: Option<Vec<u8>>,
+}
+
+impl Digests {
+    /// Checks every expected digest, the error names the first mismatch.
+    pub fn verify(&self, expected: &[Expected]) -> Result<(), String> {
+        for e in expected {
+            let actual = match e.algorithm {
+                Algorithm::Sha256 => Some(&self.sha256),
+                Algorithm::Sha512 => self.sha512.as_ref(),
+                Algorithm::Md5 => self.md5.as_ref(),
+            };
+            if actual != Some(&e.digest) {
+                return Err(format!(
+                    "{} mismatch, expected :{}: but the upload has :{}:",
+                    e.algorithm.name(),
+                    e.digest.to_base64(STANDARD),
+                    actual
+                        .map(|a| a.to_base64(STANDARD))
+                        .unwrap_or(String::new())
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hashes everything written through it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    sha256: Sha256,
+    sha512: Option<Sha512>,
+    md5: Option<Md5>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Besides sha-256, only the algorithms in `expected` are computed.
+    pub fn new(inner: W, expected: &[Expected]) -> HashingWriter<W> {
+        let wants = |a| expected.iter().any(|e| e.algorithm == a);
+        HashingWriter {
+            inner: inner,
+            sha256: Sha256::new(),
+            sha512: if wants(Algorithm::Sha512) {
+                Some(Sha512::new())
+            } else {
+                None
+            },
+            md5: if wants(Algorithm::Md5) {
+                Some(Md5::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn finish(self) -> (W, Digests) {
+        let digests = Digests {
+            sha256: self.sha256.finalize().to_vec(),
+            sha512: self.sha512.map(|h| h.finalize().to_vec()),
+            md5: self.md5.map(|h| h.finalize().to_vec()),
+        };
+        (self.inner, digests)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha256.update(&buf[0..written]);
+        if let Some(ref mut h) = self.sha512 {
+            h.update(&buf[0..written]);
+        }
+        if let Some(ref mut h) = self.md5 {
+            h.update(&buf[0..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer: [u8; 8192] = [0; 8192];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[0..read]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Formats a digest as a `Repr-Digest` header value (RFC 9530).
+pub fn repr_digest(sha256: &[u8]) -> String {
+    format!("sha-256=:{}:", sha256.to_base64(STANDARD))
+}
+
+/// Extracts the sha-256 digest from a `Repr-Digest` or `Content-Digest`
+/// header value. Other algorithms are ignored.
+pub fn parse_repr_digest(value: &str) -> Option<Vec<u8>> {
+    for member in value.split(',') {
+        let mut kv = member.trim().splitn(2, '=');
+        let algorithm = kv.next().unwrap_or("").trim();
+        let encoded = kv.next().unwrap_or("").trim().trim_matches(':');
+        if algorithm.eq_ignore_ascii_case("sha-256") {
+            return encoded.from_base64().ok();
+        }
+    }
+    None
+}
+
+/// Parses a `Content-Digest` or `Repr-Digest` header value, or the
+/// `digest` field of a form upload. Values are normally base64 between
+/// colons, plain hex is accepted as well since that's what `sha256sum`
+/// prints. Unknown algorithms are skipped, but at least one has to be
+/// understood.
+pub fn parse_expected(value: &str) -> Result<Vec<Expected>, String> {
+    let mut expected = Vec::new();
+    for member in value.split(',') {
+        let mut kv = member.trim().splitn(2, '=');
+        let algorithm = match Algorithm::from_name(kv.next().unwrap_or("").trim()) {
+            Some(a) => a,
+            None => continue,
+        };
+        let encoded = kv.next().unwrap_or("").trim();
+        let digest = if encoded.starts_with(':') {
+            encoded.trim_matches(':').from_base64().ok()
+        } else {
+            encoded.from_hex().ok()
+        };
+        match digest {
+            Some(d) => expected.push(Expected {
+                algorithm: algorithm,
+                digest: d,
+            }),
+            None => {
+                return Err(format!(
+                    "Invalid {} digest \"{}\"",
+                    algorithm.name(),
+                    encoded
+                ))
+            }
+        }
+    }
+    if expected.is_empty() {
+        return Err(format!("No supported digest in \"{}\"", value));
+    }
+    Ok(expected)
+}
+
+/// Parses a `Content-MD5` header value (RFC 1864).
+pub fn parse_content_md5(value: &str) -> Result<Expected, String> {
+    match value.trim().from_base64() {
+        Ok(d) => Ok(Expected {
+            algorithm: Algorithm::Md5,
+            digest: d,
+        }),
+        Err(_) => Err(format!("Invalid Content-MD5 \"{}\"", value)),
+    }
+}
@@ -0,0 +1,598 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use getopts::{Matches, Options};
+use hyper::client::{Body, Client, RedirectPolicy};
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use percent_encoding::{percent_decode, utf8_percent_encode};
+use regex;
+use regex::Regex;
+use rustc_serialize::json;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use checksum;
+use directory::{self, FileMeta};
+use format;
+use routes::{ErrorReply, SavedFile};
+
+/// A running na instance that the client talks to.
+#[derive(Clone)]
+struct Remote {
+    base: String,
+    token: Option<String>,
+}
+
+/// Prints the progress of a single transfer to stderr.
+struct Progress {
+    name: String,
+    total: u64,
+    done: u64,
+    enabled: bool,
+    last_print: Instant,
+}
+
+/// Counts the bytes that are read through it.
+struct ProgressReader<R: Read> {
+    inner: R,
+    progress: Progress,
+}
+
+impl Progress {
+    fn new(name: &str, total: u64, done: u64, enabled: bool) -> Progress {
+        Progress {
+            name: name.to_string(),
+            total,
+            done,
+            enabled,
+            last_print: Instant::now(),
+        }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if self.enabled && self.last_print.elapsed() >= Duration::from_millis(100) {
+            self.print();
+            self.last_print = Instant::now();
+        }
+    }
+
+    fn print(&self) {
+        let percent = if self.total > 0 {
+            self.done * 100 / self.total
+        } else {
+            100
+        };
+        let _ = write!(
+            io::stderr(),
+            "\r{}  {:3}%  {} / {}",
+            self.name,
+            percent,
+            format::size(self.done),
+            format::size(self.total)
+        );
+    }
+
+    fn finish(&self) {
+        if self.enabled {
+            self.print();
+            let _ = writeln!(io::stderr(), "");
+        } else {
+            let _ = writeln!(io::stderr(), "{}  {}", self.name, format::size(self.done));
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        Ok(read)
+    }
+}
+
+impl Remote {
+    fn new(url: &str, token: Option<String>) -> Remote {
+        let mut base = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_string()
+        } else {
+            format!("http://{}", url)
+        };
+        // The address na prints when it's started with --token has the
+        // token in the query.
+        let mut query_token = None;
+        if let Some(i) = base.find('?') {
+            for pair in base[i + 1..].split('&') {
+                if pair.starts_with("token=") {
+                    query_token = percent_decode(pair["token=".len()..].as_bytes())
+                        .decode_utf8()
+                        .ok()
+                        .map(|t| t.to_string());
+                }
+            }
+            base.truncate(i);
+        }
+        while base.ends_with('/') {
+            base.pop();
+        }
+        Remote {
+            base,
+            token: token.or(query_token),
+        }
+    }
+
+    fn client(&self) -> Client {
+        let mut client = Client::new();
+        client.set_redirect_policy(RedirectPolicy::FollowNone);
+        client
+    }
+
+    fn headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(ref t) = self.token {
+            headers.set_raw("Authorization", vec![format!("Bearer {}", t).into_bytes()]);
+        }
+        headers
+    }
+
+    fn file_url(&self, name: &str) -> String {
+        format!(
+            "{}/files/{}",
+            self.base,
+            utf8_percent_encode(name, format::PATH_SEGMENT)
+        )
+    }
+
+    fn list(&self) -> Result<Vec<FileMeta>, String> {
+        let url = format!("{}/json", self.base);
+        let mut res = match self.client().get(&*url).headers(self.headers()).send() {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Unable to reach {}, {}", self.base, e)),
+        };
+        if res.status != StatusCode::Ok {
+            return Err(format!("{} answered with {}", url, res.status));
+        }
+        let mut body = String::new();
+        if let Err(e) = res.read_to_string(&mut body) {
+            return Err(format!("Unable to read the file list, {}", e));
+        }
+        json::decode(&body).map_err(|e| format!("Unable to parse the file list, {}", e))
+    }
+
+    /// Asks the server for the sha-256 digest of a file without
+    /// downloading it.
+    fn digest(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut headers = self.headers();
+        headers.set_raw("Want-Repr-Digest", vec![b"sha-256=10".to_vec()]);
+
+        let url = self.file_url(name);
+        let res = match self.client().head(&*url).headers(headers).send() {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Unable to reach {}, {}", self.base, e)),
+        };
+        Ok(repr_digest(&res.headers))
+    }
+
+    fn download(
+        &self,
+        meta: &FileMeta,
+        dir: &Path,
+        resume: bool,
+        verify: bool,
+        progress: bool,
+    ) -> Result<(), String> {
+        // The name comes from the other server, which mustn't get to
+        // pick where on this machine the file goes.
+        let path = dir.join(directory::sanitize_name(&meta.name));
+        let offset = if resume {
+            fs::metadata(&path).map(|m| m.len()).unwrap_or(0)
+        } else {
+            0
+        };
+        // What's there may be part of another version of the file, so it
+        // is only kept if the result matches the server's digest.
+        if offset > 0 && offset <= meta.size {
+            let expected = if offset == meta.size {
+                self.digest(&meta.name)?
+            } else {
+                self.fetch(meta, &path, offset, true, progress)?
+            };
+            if same_digest(&path, &expected)? {
+                return Ok(());
+            }
+            let _ = writeln!(
+                io::stderr(),
+                "{} doesn't match the server's copy, downloading it again",
+                meta.name
+            );
+        }
+        let expected = self.fetch(meta, &path, 0, verify, progress)?;
+        if verify {
+            verify_file(&path, expected)?;
+        }
+        Ok(())
+    }
+
+    /// Downloads the file from `offset` on, appending to what's at `path`,
+    /// and returns the digest the server sent if `digest` asked for one.
+    fn fetch(
+        &self,
+        meta: &FileMeta,
+        path: &Path,
+        mut offset: u64,
+        digest: bool,
+        progress: bool,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let mut headers = self.headers();
+        if offset > 0 {
+            headers.set_raw("Range", vec![format!("bytes={}-", offset).into_bytes()]);
+        }
+        if digest {
+            headers.set_raw("Want-Repr-Digest", vec![b"sha-256=10".to_vec()]);
+        }
+
+        let url = self.file_url(&meta.name);
+        let mut res = match self.client().get(&*url).headers(headers).send() {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Unable to reach {}, {}", self.base, e)),
+        };
+        let mut file = match res.status {
+            StatusCode::PartialContent => OpenOptions::new().append(true).open(path),
+            StatusCode::Ok => {
+                offset = 0;
+                File::create(path)
+            }
+            s => return Err(format!("{} answered with {}", url, s)),
+        }
+        .map_err(|e| format!("Unable to open {}, {}", path.display(), e))?;
+
+        let expected = repr_digest(&res.headers);
+        let mut bar = Progress::new(&meta.name, meta.size, offset, progress);
+        let mut buffer: [u8; 8192] = [0; 8192];
+
+        loop {
+            let read = match res.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(r) => r,
+                Err(e) => return Err(format!("Download of {} interrupted, {}", meta.name, e)),
+            };
+            if let Err(e) = file.write_all(&buffer[0..read]) {
+                return Err(format!("Unable to write {}, {}", path.display(), e));
+            }
+            bar.advance(read as u64);
+        }
+        bar.finish();
+        Ok(expected)
+    }
+
+    /// Sends a file through the upload form. With a digest, the server
+    /// checks the file against it and refuses it if they don't match.
+    fn upload(
+        &self,
+        path: &Path,
+        digest: Option<&[u8]>,
+        progress: bool,
+    ) -> Result<Vec<SavedFile>, String> {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => return Err(format!("Invalid file name {}", path.display())),
+        };
+        let file = File::open(path).map_err(|e| format!("Unable to open {}, {}", name, e))?;
+        let size = file
+            .metadata()
+            .map_err(|e| format!("Unable to read {}, {}", name, e))?
+            .len();
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let boundary = format!("------------------------na{:x}", nanos);
+        let mut head = String::new();
+        if let Some(d) = digest {
+            head.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"digest\"\r\n\r\n{}\r\n",
+                boundary,
+                checksum::repr_digest(d)
+            ));
+        }
+        head.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"upload[]\"; \
+             filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            boundary,
+            name.replace('"', "\\\"")
+        ));
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        let len = head.len() as u64 + size + tail.len() as u64;
+
+        let mut body = Cursor::new(head.into_bytes())
+            .chain(ProgressReader {
+                inner: file,
+                progress: Progress::new(&name, size, 0, progress),
+            })
+            .chain(Cursor::new(tail.into_bytes()));
+
+        let mut headers = self.headers();
+        headers.set_raw(
+            "Content-Type",
+            vec![format!("multipart/form-data; boundary={}", boundary).into_bytes()],
+        );
+        headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+
+        let url = format!("{}/", self.base);
+        let mut res = match self
+            .client()
+            .post(&*url)
+            .headers(headers)
+            .body(Body::SizedBody(&mut body, len))
+            .send()
+        {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Unable to send {}, {}", name, e)),
+        };
+        body.get_ref().0.get_ref().1.progress.finish();
+
+        let mut answer = String::new();
+        if let Err(e) = res.read_to_string(&mut answer) {
+            return Err(format!("Unable to read the server's answer, {}", e));
+        }
+        if !res.status.is_success() {
+            let message = match json::decode::<ErrorReply>(&answer) {
+                Ok(reply) => reply.error.message,
+                Err(_) => answer.trim().to_string(),
+            };
+            return Err(format!("{} answered with {}: {}", url, res.status, message));
+        }
+        json::decode(&answer).map_err(|e| format!("Unable to parse the server's answer, {}", e))
+    }
+}
+
+fn repr_digest(headers: &Headers) -> Option<Vec<u8>> {
+    headers
+        .get_raw("Repr-Digest")
+        .and_then(|v| v.first())
+        .and_then(|v| str::from_utf8(v).ok())
+        .and_then(checksum::parse_repr_digest)
+}
+
+/// Whether the file at `path` has the expected digest. Without one, it
+/// can't be told.
+fn same_digest(path: &Path, expected: &Option<Vec<u8>>) -> Result<bool, String> {
+    match *expected {
+        Some(ref e) => checksum::sha256_file(path)
+            .map(|actual| actual == *e)
+            .map_err(|err| format!("Unable to hash {}, {}", path.display(), err)),
+        None => Ok(false),
+    }
+}
+
+fn verify_file(path: &Path, expected: Option<Vec<u8>>) -> Result<(), String> {
+    let expected = match expected {
+        Some(e) => e,
+        None => {
+            return Err(format!(
+                "The server didn't send a checksum for {}",
+                path.display()
+            ))
+        }
+    };
+    let actual = checksum::sha256_file(path)
+        .map_err(|e| format!("Unable to hash {}, {}", path.display(), e))?;
+    if actual != expected {
+        return Err(format!("Checksum mismatch for {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Turns a shell style glob ("*.jpg", "img_??.png") into a regex.
+fn glob(pattern: &str) -> Regex {
+    let re = regex::escape(pattern)
+        .replace("\\*", ".*")
+        .replace("\\?", ".");
+    Regex::new(&format!("^{}$", re)).unwrap()
+}
+
+fn collect_files(path: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    if !path.is_dir() {
+        return Err(format!("\"{}\" does not exist", path.display()));
+    }
+    if !recursive {
+        return Err(format!(
+            "\"{}\" is a directory (use -r to send its contents)",
+            path.display()
+        ));
+    }
+    let entries =
+        fs::read_dir(path).map_err(|e| format!("Unable to read {}, {}", path.display(), e))?;
+    for e in entries {
+        let e = e.map_err(|e| format!("Unable to read {}, {}", path.display(), e))?;
+        collect_files(&e.path(), recursive, files)?;
+    }
+    Ok(())
+}
+
+fn ls(remote: &Remote) -> Result<(), String> {
+    let files = remote.list()?;
+    let width = files
+        .iter()
+        .map(|f| f.name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for f in files {
+        println!(
+            "{:<width$}  {:>10}  {}",
+            f.name,
+            format::size(f.size),
+            f.modified,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+fn get(remote: &Remote, patterns: &[String], options: &Matches) -> Result<(), String> {
+    let dir = match options.opt_str("o") {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from("."),
+    };
+    let jobs = match options.opt_str("j") {
+        Some(j) => match j.parse::<usize>() {
+            Ok(j) if j > 0 => j,
+            _ => return Err(format!("Invalid number of jobs \"{}\"", j)),
+        },
+        None => 1,
+    };
+    let resume = options.opt_present("resume");
+    let verify = options.opt_present("verify");
+    let globs: Vec<Regex> = patterns.iter().map(|p| glob(p)).collect();
+
+    let files: Vec<FileMeta> = remote
+        .list()?
+        .into_iter()
+        .filter(|f| globs.is_empty() || globs.iter().any(|g| g.is_match(&f.name)))
+        .collect();
+    if files.is_empty() {
+        return Err("No matching files".to_string());
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("Unable to create {}, {}", dir.display(), e))?;
+
+    let queue = Arc::new(Mutex::new(files));
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let mut workers = Vec::new();
+
+    for _ in 0..jobs {
+        let queue = queue.clone();
+        let errors = errors.clone();
+        let remote = remote.clone();
+        let dir = dir.clone();
+
+        workers.push(thread::spawn(move || loop {
+            let meta = match queue.lock().unwrap().pop() {
+                Some(m) => m,
+                None => break,
+            };
+            if let Err(e) = remote.download(&meta, &dir, resume, verify, jobs == 1) {
+                errors.lock().unwrap().push(e);
+            }
+        }));
+    }
+    for w in workers {
+        let _ = w.join();
+    }
+
+    let errors = errors.lock().unwrap();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn send(remote: &Remote, paths: &[String], options: &Matches) -> Result<(), String> {
+    let mut files = Vec::new();
+    for p in paths {
+        collect_files(Path::new(p), options.opt_present("r"), &mut files)?;
+    }
+    if files.is_empty() {
+        return Err("Nothing to send".to_string());
+    }
+    let verify = options.opt_present("verify");
+
+    for path in files {
+        // The server refuses the upload if it doesn't arrive intact.
+        let digest = if verify {
+            Some(
+                checksum::sha256_file(&path)
+                    .map_err(|e| format!("Unable to hash {}, {}", path.display(), e))?,
+            )
+        } else {
+            None
+        };
+        for saved in remote.upload(&path, digest.as_ref().map(|d| &d[..]), true)? {
+            if saved.saved_name != saved.source_name {
+                println!("{} saved as {}", saved.source_name, saved.saved_name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn usage(program: &str, command: &str, opts: &Options) -> String {
+    let brief = match command {
+        "ls" => format!("Usage: {} ls [OPTIONS] URL", program),
+        "get" => format!("Usage: {} get [OPTIONS] URL [NAME|GLOB...]", program),
+        _ => format!("Usage: {} send [OPTIONS] URL FILE...", program),
+    };
+    opts.usage(&brief)
+}
+
+/// Runs one of the client subcommands (`ls`, `get` or `send`) against
+/// another na instance.
+pub fn run(program: &str, command: &str, args: &[String]) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message and exit");
+    opts.optopt("t", "token", "access token of the remote na", "TOKEN");
+    match command {
+        "get" => {
+            opts.optopt("o", "output", "directory to save files in", "PATH");
+            opts.optopt("j", "jobs", "number of parallel downloads", "NUMBER");
+            opts.optflag(
+                "",
+                "resume",
+                "continue where earlier downloads stopped, if the server's checksum agrees",
+            );
+            opts.optflag("", "verify", "verify the checksum of downloaded files");
+        }
+        "send" => {
+            opts.optflag("r", "recursive", "send the contents of directories");
+            opts.optflag("", "verify", "verify the checksum of sent files");
+        }
+        _ => {}
+    }
+
+    let options = opts.parse(args).map_err(|e| e.to_string())?;
+    if options.opt_present("h") {
+        println!("{}", usage(program, command, &opts));
+        return Ok(());
+    }
+    if options.free.is_empty() {
+        return Err(usage(program, command, &opts));
+    }
+    let remote = Remote::new(&options.free[0], options.opt_str("t"));
+    let rest = &options.free[1..];
+
+    match command {
+        "ls" => ls(&remote),
+        "get" => get(&remote, rest, &options),
+        _ => send(&remote, rest, &options),
+    }
+}
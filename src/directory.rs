@@ -20,110 +20,556 @@
 use chrono::datetime::DateTime;
 use chrono::naive::datetime::NaiveDateTime;
 use chrono::offset::local::Local;
-use chrono::offset::LocalResult;
 use chrono::offset::TimeZone;
 use std::fs;
-use std::path::PathBuf;
-use std::time::UNIX_EPOCH;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use format;
+use index::Index;
+use locale::{self, Lang};
+
+/// Names that belong to na itself, such as partial uploads, the trash
+/// and older versions. Nothing can be uploaded under them and they are
+/// neither listed nor served.
+pub const RESERVED_PREFIX: &'static str = ".na-";
+
+/// Uploads are written to hidden files with this prefix and only renamed
+/// to their real name once they are complete.
+const PARTIAL_PREFIX: &'static str = ".na-upload-";
+const PARTIAL_SUFFIX: &'static str = ".part";
+
+/// Partial uploads that haven't been written to for this long are left
+/// over from a crash rather than in progress in another na instance.
+const STALE_PARTIAL_AGE: u64 = 10 * 60;
+
+static PARTIAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Longest file name, in bytes, that uploads are saved under. Leaves
+/// room for a " (n)" suffix within the usual limit of 255.
+const MAX_NAME_LEN: usize = 240;
+
+/// What to do about symbolic links in the served directory.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Links are served wherever they point to.
+    Follow,
+    /// Links are only served if they point inside the served directory.
+    Within,
+    /// Links are neither listed nor served.
+    Never,
+}
+
+impl SymlinkPolicy {
+    pub fn parse(s: &str) -> Result<SymlinkPolicy, String> {
+        match s {
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "within" => Ok(SymlinkPolicy::Within),
+            "never" => Ok(SymlinkPolicy::Never),
+            _ => Err(format!(
+                "Invalid symlink policy \"{}\", expected follow, within or never",
+                s
+            )),
+        }
+    }
+}
 
 pub struct Directory {
     pub root: PathBuf,
+    /// If set, only these files are shared instead of the contents of `root`.
+    pub files: Option<Vec<PathBuf>>,
+    /// Standard input shared as a single file that can be downloaded once.
+    pub stream: Option<Stream>,
+    pub symlinks: SymlinkPolicy,
+    /// Show modification times in UTC rather than local time.
+    pub utc: bool,
+    /// Cached listing of `root`, if enabled.
+    index: Option<Arc<Index>>,
 }
 
-#[derive(RustcDecodable, RustcEncodable)]
+pub struct Stream {
+    pub name: String,
+    taken: AtomicBool,
+    source: Mutex<Option<Box<dyn Read + Send>>>,
+}
+
+/// What a file is, rather than what it's called. Once a file is gone its
+/// name can be taken by another one, which gets a different id.
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileId(u64, u64);
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    FileId(meta.dev(), meta.ino())
+}
+
+/// Without inodes, files are told apart by when they were created.
+#[cfg(not(unix))]
+fn file_id(meta: &fs::Metadata) -> FileId {
+    let created = meta
+        .created()
+        .ok()
+        .and_then(|c| c.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    FileId(created.as_secs(), created.subsec_nanos() as u64)
+}
+
+#[derive(Clone, RustcDecodable, RustcEncodable)]
 pub struct FileMeta {
     pub name: String,
     pub url: String,
     pub size: u64,
     pub modified: String,
     pub modified_raw: u64,
+    /// RFC 3339 time, with the timezone's offset.
+    pub modified_rfc3339: String,
+}
+
+impl Stream {
+    /// Shares standard input under `name`.
+    pub fn new(name: String) -> Stream {
+        Stream::from_reader(name, io::stdin())
+    }
+
+    /// Shares whatever `source` reads under `name`.
+    pub fn from_reader<R: Read + Send + 'static>(name: String, source: R) -> Stream {
+        Stream {
+            name,
+            taken: AtomicBool::new(false),
+            source: Mutex::new(Some(Box::new(source))),
+        }
+    }
+
+    pub fn is_taken(&self) -> bool {
+        self.taken.load(Ordering::SeqCst)
+    }
+
+    /// Claims the stream for a single downloader. Only the first call succeeds.
+    pub fn take(&self) -> bool {
+        !self.taken.swap(true, Ordering::SeqCst)
+    }
+
+    /// Copies the stream to `out` and returns the number of bytes written.
+    /// It can only be read once.
+    pub fn copy_to<W: Write>(&self, out: &mut W) -> io::Result<u64> {
+        let mut input = match self.source.lock().unwrap().take() {
+            Some(s) => s,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "already read")),
+        };
+        let mut buffer: [u8; 8192] = [0; 8192];
+        let mut total: u64 = 0;
+
+        loop {
+            let read = input.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            out.write_all(&buffer[0..read])?;
+            total += read as u64;
+        }
+        Ok(total)
+    }
+}
+
+pub fn local_date(time: SystemTime) -> Option<DateTime<Local>> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_unix) => {
+            let ndt = NaiveDateTime::from_timestamp(
+                since_unix.as_secs() as i64,
+                since_unix.subsec_nanos(),
+            );
+            Some(Local.from_utc_datetime(&ndt))
+        }
+        Err(_) => None,
+    }
+}
+
+/// A name that refers to an entry of the served directory itself and
+/// can't be used to climb out of it.
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(|c| c == '/' || c == '\\' || c == '\0')
+}
+
+fn is_reserved_on_windows(name: &str) -> bool {
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or("")
+        .trim_end()
+        .to_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        s if s.len() == 4 && (s.starts_with("COM") || s.starts_with("LPT")) => {
+            s[3..].chars().all(|c| c >= '1' && c <= '9')
+        }
+        _ => false,
+    }
+}
+
+/// Turns a client supplied file name into one that can safely be created
+/// in the served directory on any platform: directories are dropped, and
+/// control characters, characters Windows doesn't allow, trailing dots and
+/// spaces, reserved device names and the names na keeps its own files
+/// under are taken care of.
+pub fn sanitize_name(name: &str) -> String {
+    // Some browsers send the full path of the file.
+    let name = name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let mut clean: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_control() => '_',
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+    while clean.ends_with('.') || clean.ends_with(' ') {
+        clean.pop();
+    }
+    if clean.is_empty() {
+        clean = "unnamed".to_string();
+    }
+    if is_reserved_on_windows(&clean) || clean.starts_with(RESERVED_PREFIX) {
+        clean.insert(0, '_');
+    }
+    while clean.len() > MAX_NAME_LEN {
+        clean.pop();
+    }
+    clean
+}
+
+fn is_partial(path: &Path) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n.starts_with(PARTIAL_PREFIX) && n.ends_with(PARTIAL_SUFFIX),
+        None => false,
+    }
+}
+
+/// Gives `from` the name `to`, failing with `AlreadyExists` if `to` is
+/// taken. A hard link does that in a single step, filesystems without
+/// them get the name reserved with an exclusive create first.
+fn rename_exclusive(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(_) => {
+            let _ = fs::remove_file(from);
+            return Ok(());
+        }
+        Err(e) => {
+            if e.kind() == io::ErrorKind::AlreadyExists {
+                return Err(e);
+            }
+        }
+    }
+    OpenOptions::new().write(true).create_new(true).open(to)?;
+    fs::rename(from, to)
+}
+
+/// Describes one of the files given on the command line.
+fn shared_file_meta(path: &Path, utc: bool) -> Option<FileMeta> {
+    let meta = fs::metadata(path).ok()?;
+    if !meta.is_file() {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?.to_string();
+    Some(file_meta(name, meta.len(), meta.modified().ok()?, utc))
+}
+
+fn file_meta(name: String, size: u64, modified: SystemTime, utc: bool) -> FileMeta {
+    let secs = match modified.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    };
+    FileMeta {
+        url: format!("/files/{}", name),
+        name,
+        size,
+        modified: locale::timestamp(secs, utc, Lang::En),
+        modified_raw: secs,
+        modified_rfc3339: locale::rfc3339(secs, utc),
+    }
 }
 
 impl Directory {
     pub fn new(root: PathBuf) -> Directory {
-        Directory { root }
+        Directory {
+            root,
+            files: None,
+            stream: None,
+            symlinks: SymlinkPolicy::Within,
+            utc: false,
+            index: None,
+        }
+    }
+
+    /// Keeps a cached listing of the served directory instead of reading
+    /// it for every request. Changes are picked up by watching the
+    /// directory. If that fails the index is still used, but changes na
+    /// didn't make itself only show up after it's read again, once every
+    /// `rescan`.
+    pub fn cache_index(&mut self, rescan: Duration) -> Result<(), String> {
+        let index = Arc::new(Index::new(rescan));
+        self.index = Some(index.clone());
+        Index::watch(&index, &self.root)
+    }
+
+    /// Lets the index know that `name` was changed by na itself, without
+    /// waiting for the change to be noticed.
+    pub fn touch(&self, name: &str) {
+        if let Some(ref index) = self.index {
+            index.touch(name);
+        }
     }
 
     pub fn list_available_resources(&self) -> Vec<FileMeta> {
+        let mut files: Vec<FileMeta> = match self.files {
+            Some(ref paths) => paths
+                .iter()
+                .filter_map(|p| shared_file_meta(p, self.utc))
+                .collect(),
+            None => match self.index {
+                Some(ref index) => index.with(
+                    || self.scan(),
+                    |name| self.stat(name),
+                    |files| files.values().cloned().collect(),
+                ),
+                None => self.scan(),
+            },
+        };
+        if let Some(meta) = self.stream_meta() {
+            files.push(meta);
+        }
+        files
+    }
+
+    /// Returns the file called `name` if it's one of the listed ones.
+    /// Unlike going through the listing, this doesn't have to look at
+    /// every other file.
+    pub fn lookup(&self, name: &str) -> Option<FileMeta> {
+        let found = match self.files {
+            Some(ref paths) => paths
+                .iter()
+                .find(|p| p.file_name().and_then(|n| n.to_str()) == Some(name))
+                .and_then(|p| shared_file_meta(p, self.utc)),
+            None => match self.index {
+                Some(ref index) => index.with(
+                    || self.scan(),
+                    |n| self.stat(n),
+                    |files| files.get(name).cloned(),
+                ),
+                None => self.stat(name),
+            },
+        };
+        found.or_else(|| self.stream_meta().filter(|m| m.name == name))
+    }
+
+    fn stream_meta(&self) -> Option<FileMeta> {
+        match self.stream {
+            Some(ref stream) if !stream.is_taken() => Some(file_meta(
+                stream.name.clone(),
+                0,
+                SystemTime::now(),
+                self.utc,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Reads every entry of the served directory.
+    fn scan(&self) -> Vec<FileMeta> {
         let mut files: Vec<FileMeta> = Vec::new();
         let paths = fs::read_dir(&(self.root)).unwrap();
 
         for p in paths {
             let pu = p.unwrap();
-            if pu.file_type().unwrap().is_file() {
-                let date = match pu.metadata().unwrap().modified() {
-                    Ok(systime) => match systime.duration_since(UNIX_EPOCH) {
-                        Ok(since_unix) => {
-                            let ndt = NaiveDateTime::from_timestamp(
-                                since_unix.as_secs() as i64,
-                                since_unix.subsec_nanos(),
-                            );
-                            match Local.offset_from_local_datetime(&ndt) {
-                                LocalResult::Single(t) => Some(DateTime::from_utc(ndt, t)),
-                                _ => None,
-                            }
-                        }
-                        Err(_) => None,
-                    },
-                    Err(_) => None,
-                };
-                files.push(FileMeta {
-                    name: pu.file_name().into_string().unwrap(),
-                    url: format!("/files/{}", pu.file_name().into_string().unwrap()),
-                    size: pu.metadata().unwrap().len(),
-                    modified: match date {
-                        Some(d) => format::date(&d),
-                        None => "n/a".to_string(),
-                    },
-                    modified_raw: pu
-                        .metadata()
-                        .unwrap()
-                        .modified()
-                        .unwrap()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                });
+            let name = match pu.file_name().into_string() {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+            if name.starts_with(RESERVED_PREFIX) {
+                continue;
+            }
+            // Links are only listed if the policy allows serving them.
+            let meta = if pu.file_type().unwrap().is_symlink() {
+                match self.resolve(&name).and_then(|p| fs::metadata(p).ok()) {
+                    Some(m) => m,
+                    None => continue,
+                }
+            } else {
+                pu.metadata().unwrap()
+            };
+            if meta.is_file() {
+                files.push(file_meta(
+                    name,
+                    meta.len(),
+                    meta.modified().unwrap(),
+                    self.utc,
+                ));
             }
         }
         files
     }
 
-    pub fn full_path(&self, name: &str) -> PathBuf {
-        let mut path = PathBuf::new();
-        path.push(self.root.to_str().unwrap());
-        path.push(name.to_string());
-        path
+    /// Looks at a single entry of the served directory, the same way
+    /// `scan` does.
+    fn stat(&self, name: &str) -> Option<FileMeta> {
+        if name.starts_with(RESERVED_PREFIX) {
+            return None;
+        }
+        let meta = fs::metadata(self.resolve(name)?).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        Some(file_meta(
+            name.to_string(),
+            meta.len(),
+            meta.modified().ok()?,
+            self.utc,
+        ))
     }
 
-    pub fn get_available_name(&self, name: &str) -> String {
-        let files = self.list_available_resources();
-        let mut aname = name.to_string();
-        let mut num = 1;
-        while self.name_exists(&aname, &files) {
-            aname = format!("{} ({})", name, num);
-            num += 1;
+    /// Maps a file name to its path. This is the only way from a name a
+    /// client sent to a path: anything but a plain name of an entry in
+    /// the served directory, and links the symlink policy doesn't allow,
+    /// are refused.
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        // Explicitly shared files were picked by whoever started na.
+        if let Some(ref paths) = self.files {
+            for p in paths {
+                if p.file_name().and_then(|n| n.to_str()) == Some(name) {
+                    return Some(p.clone());
+                }
+            }
+        }
+        if !is_plain_name(name) || name.starts_with(RESERVED_PREFIX) {
+            return None;
+        }
+        let path = self.root.join(name);
+        let is_link = fs::symlink_metadata(&path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        if !is_link {
+            return Some(path);
+        }
+        match self.symlinks {
+            SymlinkPolicy::Follow => Some(path),
+            SymlinkPolicy::Never => None,
+            SymlinkPolicy::Within => {
+                let target = fs::canonicalize(&path).ok()?;
+                let root = fs::canonicalize(&self.root).ok()?;
+                if target.starts_with(&root) {
+                    Some(path)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// The id of the file that's called `name` now, if there is one.
+    pub fn file_id(&self, name: &str) -> Option<FileId> {
+        let path = self.resolve(name)?;
+        fs::metadata(&path).ok().map(|m| file_id(&m))
+    }
+
+    /// Creates a new hidden file to write an upload to. It lives in the
+    /// served directory so that it can be renamed into place.
+    pub fn create_partial(&self) -> io::Result<(PathBuf, File)> {
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+            let name = format!(
+                "{}{}-{}-{}{}",
+                PARTIAL_PREFIX,
+                process::id(),
+                PARTIAL_COUNTER.fetch_add(1, Ordering::SeqCst),
+                nanos,
+                PARTIAL_SUFFIX
+            );
+            let path = self.root.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(f) => return Ok((path, f)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
         }
-        return aname;
     }
 
-    pub fn get_resource<'a>(&self, name: &str, files: &'a Vec<FileMeta>) -> Option<&'a FileMeta> {
-        for file in files {
-            if file.name == name {
-                return Some(&file);
+    /// Flushes a finished partial upload to disk and moves it to `name`,
+    /// or to the first free "name (n)" unless `overwrite` is set. `name`
+    /// has to have gone through `sanitize_name`. Returns the name the
+    /// file was saved under.
+    pub fn commit_partial(
+        &self,
+        partial: &Path,
+        name: &str,
+        overwrite: bool,
+    ) -> io::Result<String> {
+        OpenOptions::new().write(true).open(partial)?.sync_all()?;
+        if overwrite {
+            fs::rename(partial, self.root.join(name))?;
+            self.touch(name);
+            return Ok(name.to_string());
+        }
+        let mut candidate = name.to_string();
+        let mut num = 1;
+        loop {
+            match rename_exclusive(partial, &self.root.join(&candidate)) {
+                Ok(_) => {
+                    self.touch(&candidate);
+                    return Ok(candidate);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    candidate = format!("{} ({})", name, num);
+                    num += 1;
+                }
+                Err(e) => return Err(e),
             }
         }
-        None
     }
 
-    fn name_exists(&self, name: &str, files: &Vec<FileMeta>) -> bool {
-        for v in files {
-            if v.name == name {
-                return true;
+    /// Like `commit_partial`, but fails with `AlreadyExists` rather than
+    /// save under another name when `name` is taken.
+    pub fn commit_partial_exclusive(&self, partial: &Path, name: &str) -> io::Result<()> {
+        OpenOptions::new().write(true).open(partial)?.sync_all()?;
+        rename_exclusive(partial, &self.root.join(name))?;
+        self.touch(name);
+        Ok(())
+    }
+
+    /// Removes partial uploads left behind by an earlier run. Returns how
+    /// many were removed.
+    pub fn remove_stale_partials(&self) -> usize {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(e) => e,
+            Err(_) => return 0,
+        };
+        let mut removed = 0;
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !is_partial(&path) {
+                continue;
+            }
+            let age = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|m| m.elapsed().ok());
+            if age.map_or(true, |a| a >= Duration::from_secs(STALE_PARTIAL_AGE)) {
+                if fs::remove_file(&path).is_ok() {
+                    removed += 1;
+                }
             }
         }
-        return false;
+        removed
     }
 }
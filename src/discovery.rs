@@ -0,0 +1,174 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use getopts::Options;
+use rustc_serialize::json;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ip;
+
+/// UDP port that beacons are sent to and `na discover` listens on.
+pub const BEACON_PORT: u16 = 8887;
+
+const BEACON_INTERVAL: u64 = 2;
+
+/// Announcement sent by every running na.
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Beacon {
+    pub service: String,
+    pub name: String,
+    pub url: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+}
+
+impl Beacon {
+    pub fn new(name: String, url: String, version: &str, capabilities: Vec<String>) -> Beacon {
+        Beacon {
+            service: "na".to_string(),
+            name,
+            url,
+            version: version.to_string(),
+            capabilities,
+        }
+    }
+}
+
+/// Periodically broadcasts the beacon on every local IPv4 segment and to
+/// the loopback interface, so that instances on the same machine can be
+/// found as well.
+pub fn announce(beacon: Beacon, port: u16) -> Result<(), String> {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Unable to open the beacon socket, {}", e)),
+    };
+    if let Err(e) = socket.set_broadcast(true) {
+        return Err(format!("Unable to enable broadcasts, {}", e));
+    }
+    let payload = json::encode(&beacon).unwrap();
+
+    thread::spawn(move || loop {
+        let mut targets = ip::get_broadcast_addrs();
+        targets.push(Ipv4Addr::new(127, 0, 0, 1));
+        for t in targets {
+            let _ = socket.send_to(payload.as_bytes(), SocketAddrV4::new(t, port));
+        }
+        thread::sleep(Duration::from_secs(BEACON_INTERVAL));
+    });
+    Ok(())
+}
+
+/// Listens for beacons for the given amount of time and returns every
+/// distinct instance that was heard.
+pub fn listen(port: u16, wait: Duration) -> Result<Vec<(SocketAddr, Beacon)>, String> {
+    let socket = match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Unable to listen on UDP port {}, {}", port, e)),
+    };
+    let started = Instant::now();
+    let mut found: Vec<(SocketAddr, Beacon)> = Vec::new();
+    let mut buffer: [u8; 2048] = [0; 2048];
+
+    while started.elapsed() < wait {
+        let left = match wait.checked_sub(started.elapsed()) {
+            Some(l) if l > Duration::from_millis(0) => l,
+            _ => break,
+        };
+        let _ = socket.set_read_timeout(Some(left));
+        let (len, from) = match socket.recv_from(&mut buffer) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let beacon: Beacon = match str::from_utf8(&buffer[0..len])
+            .ok()
+            .and_then(|b| json::decode(b).ok())
+        {
+            Some(b) => b,
+            None => continue,
+        };
+        if beacon.service == "na" && !found.iter().any(|&(_, ref f)| f.url == beacon.url) {
+            found.push((from, beacon));
+        }
+    }
+    Ok(found)
+}
+
+/// Runs the `discover` subcommand.
+pub fn run(program: &str, args: &[String]) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help message and exit");
+    opts.optopt(
+        "w",
+        "wait",
+        "seconds to listen for announcements (default 3)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "beacon-port",
+        "UDP port to listen on (default 8887)",
+        "PORT",
+    );
+    opts.optflag("", "json", "print the found instances as JSON");
+
+    let options = opts.parse(args).map_err(|e| e.to_string())?;
+    if options.opt_present("h") {
+        let brief = format!("Usage: {} discover [OPTIONS]", program);
+        println!("{}", opts.usage(&brief));
+        return Ok(());
+    }
+    let wait = match options.opt_str("w") {
+        Some(w) => match w.parse::<u64>() {
+            Ok(w) => w,
+            Err(_) => return Err(format!("Invalid number of seconds \"{}\"", w)),
+        },
+        None => 3,
+    };
+    let port = match options.opt_str("beacon-port") {
+        Some(p) => match p.parse::<u16>() {
+            Ok(p) => p,
+            Err(_) => return Err(format!("Invalid port \"{}\"", p)),
+        },
+        None => BEACON_PORT,
+    };
+
+    let found = listen(port, Duration::from_secs(wait))?;
+    if options.opt_present("json") {
+        let beacons: Vec<Beacon> = found.into_iter().map(|(_, b)| b).collect();
+        println!("{}", json::encode(&beacons).unwrap());
+        return Ok(());
+    }
+    if found.is_empty() {
+        println!("No na instances found.");
+    }
+    for (from, b) in found {
+        println!(
+            "{}  {}  (from {}, na {}) [{}]",
+            b.name,
+            b.url,
+            from.ip(),
+            b.version,
+            b.capabilities.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -17,78 +17,59 @@
  * along with na.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use chrono::datetime::DateTime;
-use chrono::offset::local::Local;
-use chrono::Datelike;
-use chrono::Timelike;
-use chrono::Weekday;
 use directory::FileMeta;
 use hyper::method::Method;
-use month::{month, Month};
+use locale::{self, Style, Units};
+use markdown;
 use mustache::{self, MapBuilder, VecBuilder};
-use std::convert::TryFrom;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use snippet::Snippet;
 use std::string::String;
+use trash::TrashEntry;
+use versions::Revision;
 
-fn size(bytes: u64) -> String {
-    return match bytes {
-        b if b < 1000 => {
-            format!("{} b", b)
-        }
-        b @ 1000..=999999 => {
-            let kb = b / 1000;
-            let rm = (b % 1000) / 10;
-            format!("{}.{} Kb", kb, rm)
-        }
-        b @ 1000000..=999999999 => {
-            let mb = b / 1000000;
-            let rm = (b % 1000000) / 10000;
-            format!("{}.{} Mb", mb, rm)
-        }
-        b if b > 999999999 => {
-            let gb = b / 1000000000;
-            let rm = (b % 1000000000) / 10000000;
-            format!("{}.{} Gb", gb, rm)
-        }
-        _ => {
-            format!("n/a")
-        }
-    };
-}
+/// Characters escaped in a path segment, which is all of them but the
+/// unreserved ones of RFC 3986.
+pub const PATH_SEGMENT: &'static AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
-pub fn weekday(weekday: Weekday) -> String {
-    match weekday {
-        Weekday::Mon => "Mon",
-        Weekday::Tue => "Tue",
-        Weekday::Wed => "Wed",
-        Weekday::Thu => "Thu",
-        Weekday::Fri => "Fri",
-        Weekday::Sat => "Sat",
-        Weekday::Sun => "Sun",
-    }
-    .to_string()
+/// Formats a size in SI units.
+pub fn size(bytes: u64) -> String {
+    locale::size(bytes, Units::Si)
 }
 
-pub fn date(date: &DateTime<Local>) -> String {
-    format!(
-        "{}, {:02} {:02} {}  {:02}:{:02}:{:02}",
-        weekday(date.weekday()),
-        month(Month::try_from(date.month()).expect("Could not parse date.month u32 into Month?")),
-        date.day(),
-        date.year(),
-        date.hour(),
-        date.minute(),
-        date.second()
-    )
+/// What the main page offers besides the files and snippets.
+pub struct Page {
+    pub delete: bool,
+    pub showdir: bool,
+    pub upload: bool,
+    pub dropbox: bool,
+    pub dir: String,
+    pub readme: Option<String>,
+    pub trash: bool,
+    pub versions: bool,
 }
 
 pub fn html(
     template: &str,
     res: &Vec<FileMeta>,
-    del: bool,
-    show: bool,
-    no_upload: bool,
-    dir: String,
+    snippets: &Vec<Snippet>,
+    page: Page,
+    style: &Style,
 ) -> String {
+    let Page {
+        delete: del,
+        showdir: show,
+        upload,
+        dropbox,
+        dir,
+        readme,
+        trash,
+        versions,
+    } = page;
     let root = MapBuilder::new()
         .insert_vec("files", |_| {
             let mut data = VecBuilder::new();
@@ -97,12 +78,41 @@ pub fn html(
                     builder
                         .insert_str("url", format!("/files/{}", name.name))
                         .insert_str("name", name.name.clone())
-                        .insert_str("size", size(name.size))
+                        .insert_str("size", style.size(name.size))
                         .insert_bool("delete", del)
+                        .insert_bool("markdown", markdown::is_markdown(&name.name))
+                        .insert_bool("versions", versions)
+                        .insert_str(
+                            "versions_url",
+                            format!(
+                                "/versions/{}",
+                                utf8_percent_encode(&name.name, PATH_SEGMENT)
+                            ),
+                        )
                         .insert_str("dir", "bla")
                         .insert_str("size-bytes", format!("{}", name.size))
                         .insert_str("time", format!("{}", name.modified_raw))
-                        .insert_str("modified", name.modified.clone())
+                        .insert_str("modified", style.timestamp(name.modified_raw))
+                });
+            }
+            data
+        })
+        .insert_vec("snippets", |_| {
+            let mut data = VecBuilder::new();
+            for snippet in snippets {
+                data = data.push_map(|builder| {
+                    builder
+                        .insert_str("id", format!("{}", snippet.id))
+                        .insert_str("url", snippet.url.clone())
+                        .insert_str("text", snippet.text.clone())
+                        .insert_bool("delete", del)
+                        .insert_str(
+                            "expires",
+                            match snippet.expires {
+                                Some(e) => style.timestamp(e),
+                                None => "never".to_string(),
+                            },
+                        )
                 });
             }
             data
@@ -111,9 +121,89 @@ pub fn html(
             MapBuilder::new()
                 .insert_bool("showdir", show)
                 .insert_bool("delete", del)
+                .insert_bool("trash", trash)
                 .insert_str("dir", dir.clone())
         })
-        .insert_bool("upload", !no_upload)
+        .insert_str("lang", style.lang.code())
+        .insert_bool("upload", upload)
+        .insert_bool("dropbox", dropbox)
+        .insert_bool("has_readme", readme.is_some())
+        .insert_str("readme", readme.unwrap_or(String::new()))
+        .build();
+
+    let mut buff: Vec<u8> = Vec::new();
+    let template = mustache::compile_str(template).expect("could not compile str");
+    template.render_data(&mut buff, &root);
+
+    String::from_utf8(buff).unwrap()
+}
+
+pub fn trash(template: &str, entries: &Vec<TrashEntry>, style: &Style) -> String {
+    let root = MapBuilder::new()
+        .insert_vec("entries", |_| {
+            let mut data = VecBuilder::new();
+            for entry in entries {
+                data = data.push_map(|builder| {
+                    builder
+                        .insert_str("id", entry.id.clone())
+                        .insert_str("name", entry.name.clone())
+                        .insert_str("size", style.size(entry.size))
+                        .insert_str("deleted", entry.deleted.clone())
+                });
+            }
+            data
+        })
+        .insert_bool("empty", entries.is_empty())
+        .build();
+
+    let mut buff: Vec<u8> = Vec::new();
+    let template = mustache::compile_str(template).expect("could not compile str");
+    template.render_data(&mut buff, &root);
+
+    String::from_utf8(buff).unwrap()
+}
+
+pub fn versions(
+    template: &str,
+    name: &str,
+    revisions: &Vec<Revision>,
+    restore: bool,
+    style: &Style,
+) -> String {
+    let encoded = utf8_percent_encode(name, PATH_SEGMENT).to_string();
+    let root = MapBuilder::new()
+        .insert_str("name", name)
+        .insert_vec("revisions", |_| {
+            let mut data = VecBuilder::new();
+            for r in revisions {
+                data = data.push_map(|builder| {
+                    builder
+                        .insert_str("version", format!("{}", r.version))
+                        .insert_str("url", format!("/files/{}?version={}", encoded, r.version))
+                        .insert_str("restore", format!("/versions/{}/{}", encoded, r.version))
+                        .insert_str("size", style.size(r.size))
+                        .insert_str("modified", r.modified.clone())
+                        .insert_bool("current", r.current)
+                        .insert_bool("can_restore", restore && !r.current)
+                });
+            }
+            data
+        })
+        .build();
+
+    let mut buff: Vec<u8> = Vec::new();
+    let template = mustache::compile_str(template).expect("could not compile str");
+    template.render_data(&mut buff, &root);
+
+    String::from_utf8(buff).unwrap()
+}
+
+/// A single rendered file, e.g. a Markdown document.
+pub fn document(template: &str, name: &str, url: &str, content: &str) -> String {
+    let root = MapBuilder::new()
+        .insert_str("name", name)
+        .insert_str("url", url)
+        .insert_str("content", content)
         .build();
 
     let mut buff: Vec<u8> = Vec::new();
@@ -130,3 +220,235 @@ pub fn file_list(res: &Vec<FileMeta>) -> String {
     }
     response
 }
+
+/// File extensions of the audio and video files that go in a playlist.
+const MEDIA_EXTENSIONS: &'static [&'static str] = &[
+    "aac", "flac", "m4a", "mp3", "oga", "ogg", "opus", "wav", "wma", "avi", "flv", "m4v", "mkv",
+    "mov", "mp4", "mpeg", "mpg", "ogv", "webm", "wmv",
+];
+
+pub fn is_media(name: &str) -> bool {
+    match name.rfind('.') {
+        Some(i) => MEDIA_EXTENSIONS.contains(&&*name[i + 1..].to_lowercase()),
+        None => false,
+    }
+}
+
+/// Absolute URL of a file, for listings that are read by other
+/// programs. `query` is appended as is.
+fn absolute_url(base: &str, name: &str, query: &str) -> String {
+    format!(
+        "{}/files/{}{}",
+        base,
+        utf8_percent_encode(name, PATH_SEGMENT),
+        query
+    )
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn csv(res: &Vec<FileMeta>, base: &str, query: &str) -> String {
+    let mut response = String::from("name,url,size,modified\r\n");
+    for meta in res {
+        response.push_str(&format!(
+            "{},{},{},{}\r\n",
+            csv_field(&meta.name),
+            csv_field(&absolute_url(base, &meta.name, query)),
+            meta.size,
+            locale::rfc3339(meta.modified_raw, true)
+        ));
+    }
+    response
+}
+
+pub fn xml(res: &Vec<FileMeta>, base: &str, query: &str) -> String {
+    let mut response = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<files>\n");
+    for meta in res {
+        response.push_str(&format!(
+            "  <file name=\"{}\" url=\"{}\" size=\"{}\" modified=\"{}\"/>\n",
+            xml_escape(&meta.name),
+            xml_escape(&absolute_url(base, &meta.name, query)),
+            meta.size,
+            locale::rfc3339(meta.modified_raw, true)
+        ));
+    }
+    response.push_str("</files>\n");
+    response
+}
+
+/// An Atom feed with an entry for every file, newest first, so that new
+/// uploads show up in feed readers.
+pub fn atom(res: &Vec<FileMeta>, title: &str, base: &str, query: &str) -> String {
+    let mut files: Vec<&FileMeta> = res.iter().collect();
+    files.sort_by(|a, b| b.modified_raw.cmp(&a.modified_raw));
+    let updated = files.first().map_or(0, |f| f.modified_raw);
+
+    let mut response = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    response.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    response.push_str(&format!("  <title>{}</title>\n", xml_escape(title)));
+    response.push_str(&format!("  <id>{}/feed.atom</id>\n", xml_escape(base)));
+    response.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}/feed.atom{}\"/>\n",
+        xml_escape(base),
+        xml_escape(query)
+    ));
+    response.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        locale::rfc3339(updated, true)
+    ));
+    response.push_str("  <author><name>na</name></author>\n");
+    for meta in files {
+        let url = absolute_url(base, &meta.name, query);
+        // A file that's replaced becomes a new entry.
+        let id = format!(
+            "{}#{}",
+            absolute_url(base, &meta.name, ""),
+            meta.modified_raw
+        );
+        response.push_str(&format!(
+            "  <entry>\n    <title>{}</title>\n    <id>{}</id>\n    \
+             <link href=\"{}\" length=\"{}\"/>\n    <updated>{}</updated>\n    \
+             <summary>{}</summary>\n  </entry>\n",
+            xml_escape(&meta.name),
+            xml_escape(&id),
+            xml_escape(&url),
+            meta.size,
+            locale::rfc3339(meta.modified_raw, true),
+            size(meta.size)
+        ));
+    }
+    response.push_str("</feed>\n");
+    response
+}
+
+/// An M3U playlist of the audio and video files, sorted by name.
+pub fn m3u(res: &Vec<FileMeta>, base: &str, query: &str) -> String {
+    let mut files: Vec<&FileMeta> = res.iter().filter(|f| is_media(&f.name)).collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut response = String::from("#EXTM3U\n");
+    for meta in files {
+        response.push_str(&format!(
+            "#EXTINF:-1,{}\n{}\n",
+            meta.name.replace(|c| c == '\n' || c == '\r', " "),
+            absolute_url(base, &meta.name, query)
+        ));
+    }
+    response
+}
+
+/// Lists the files as a table with aligned columns, for terminals.
+pub fn text_table(res: &Vec<FileMeta>, style: &Style, base: &str, query: &str) -> String {
+    let mut files: Vec<&FileMeta> = res.iter().collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "SIZE".to_string(),
+        "MODIFIED".to_string(),
+        "URL".to_string(),
+    ]];
+    for meta in files {
+        rows.push([
+            meta.name.replace(|c: char| c.is_control(), "?"),
+            style.size(meta.size),
+            style.timestamp(meta.modified_raw),
+            absolute_url(base, &meta.name, query),
+        ]);
+    }
+    let mut widths = [0; 3];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut response = String::new();
+    for row in &rows {
+        response.push_str(&format!(
+            "{:<w0$}  {:>w1$}  {:<w2$}  {}\n",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        ));
+    }
+    response
+}
+
+/// Quotes `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Curl commands for using this server, filled in with its address.
+/// `example` is the name of a shared file to show downloading.
+pub fn help(
+    base: &str,
+    token: Option<&str>,
+    example: Option<&str>,
+    upload: bool,
+    delete: bool,
+) -> String {
+    let curl = match token {
+        Some(t) => format!(
+            "curl -H {}",
+            shell_quote(&format!("Authorization: Bearer {}", t))
+        ),
+        None => "curl".to_string(),
+    };
+    let example = example.unwrap_or("example.txt");
+    let url = absolute_url(base, example, "");
+
+    let mut help = format!("na is serving files at {}/\n\n", base);
+    help.push_str(&format!(
+        "List the shared files:\n  {c} {b}/\n  {c} {b}/json\n\n",
+        c = curl,
+        b = base
+    ));
+    help.push_str(&format!(
+        "Download a file:\n  {} -o {} {}\n\n",
+        curl,
+        shell_quote(example),
+        url
+    ));
+    if upload {
+        help.push_str(&format!(
+            "Upload files:\n  {c} -F upload=@example.txt {b}/\n  \
+             {c} -T example.txt {b}/files/example.txt\n\n",
+            c = curl,
+            b = base
+        ));
+    }
+    if delete {
+        help.push_str(&format!("Delete a file:\n  {} -X DELETE {}\n\n", curl, url));
+    }
+    help.push_str(&format!(
+        "Other formats of the list:\n  {c} {b}/list.csv\n  {c} {b}/list.xml\n  \
+         {c} {b}/feed.atom\n  {c} {b}/playlist.m3u8\n",
+        c = curl,
+        b = base
+    ));
+    help
+}
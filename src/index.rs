@@ -0,0 +1,146 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use notify::{op, raw_watcher, RawEvent, RecursiveMode, Watcher};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use directory::FileMeta;
+
+/// How often the served directory is read again when changes to it
+/// can't be watched.
+pub const DEFAULT_RESCAN: u64 = 30;
+
+/// How often the served directory is read again while it's watched,
+/// in case the watcher missed something.
+pub const WATCHED_RESCAN: u64 = 600;
+
+/// Cached listing of the served directory, so that requests don't have
+/// to read all of it. It's kept up to date by watching the directory,
+/// and by reading it again every so often, much less often when it's
+/// watched.
+pub struct Index {
+    state: Mutex<State>,
+    rescan: Duration,
+    watching: AtomicBool,
+}
+
+struct State {
+    files: HashMap<String, FileMeta>,
+    /// When the directory was last read in full, if it has been yet.
+    scanned: Option<Instant>,
+    /// Names that changed since and have to be looked at again.
+    dirty: HashSet<String>,
+}
+
+impl Index {
+    pub fn new(rescan: Duration) -> Index {
+        Index {
+            state: Mutex::new(State {
+                files: HashMap::new(),
+                scanned: None,
+                dirty: HashSet::new(),
+            }),
+            rescan: rescan,
+            watching: AtomicBool::new(false),
+        }
+    }
+
+    /// Watches `root` and marks whatever changes in it as dirty.
+    pub fn watch(index: &Arc<Index>, root: &Path) -> Result<(), String> {
+        let (tx, rx) = channel::<RawEvent>();
+        let mut watcher = raw_watcher(tx).map_err(|e| e.to_string())?;
+        watcher
+            .watch(root, RecursiveMode::NonRecursive)
+            .map_err(|e| e.to_string())?;
+        index.watching.store(true, Ordering::SeqCst);
+
+        let index = index.clone();
+        thread::spawn(move || {
+            // Events stop once the watcher is dropped.
+            let _watcher = watcher;
+            for event in rx {
+                let name = event
+                    .path
+                    .as_ref()
+                    .and_then(|p| p.file_name())
+                    .and_then(|n| n.to_str());
+                // Events were dropped, or couldn't be read, when the
+                // system asks for a rescan.
+                let overflow = event.op.map_or(true, |o| o.contains(op::RESCAN));
+                match name {
+                    Some(n) if !overflow => index.touch(n),
+                    _ => index.invalidate(),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Marks `name` as changed, so that it's looked at again before the
+    /// index is used next.
+    pub fn touch(&self, name: &str) {
+        self.state.lock().unwrap().dirty.insert(name.to_string());
+    }
+
+    /// Has the whole directory read again before the index is used next.
+    pub fn invalidate(&self) {
+        self.state.lock().unwrap().scanned = None;
+    }
+
+    /// Brings the index up to date and runs `f` on it. `scan` reads the
+    /// whole directory, `stat` a single entry of it.
+    pub fn with<T, S, L, F>(&self, scan: S, stat: L, f: F) -> T
+    where
+        S: FnOnce() -> Vec<FileMeta>,
+        L: Fn(&str) -> Option<FileMeta>,
+        F: FnOnce(&HashMap<String, FileMeta>) -> T,
+    {
+        let mut state = self.state.lock().unwrap();
+        let rescan = if self.watching.load(Ordering::SeqCst) {
+            cmp::max(self.rescan, Duration::from_secs(WATCHED_RESCAN))
+        } else {
+            self.rescan
+        };
+        let stale = match state.scanned {
+            Some(t) => t.elapsed() >= rescan,
+            None => true,
+        };
+        if stale {
+            state.files = scan().into_iter().map(|m| (m.name.clone(), m)).collect();
+            state.scanned = Some(Instant::now());
+            state.dirty.clear();
+        } else if !state.dirty.is_empty() {
+            let dirty: Vec<String> = state.dirty.drain().collect();
+            for name in dirty {
+                match stat(&name) {
+                    Some(meta) => state.files.insert(name, meta),
+                    None => state.files.remove(&name),
+                };
+            }
+        }
+        f(&state.files)
+    }
+}
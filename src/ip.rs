@@ -18,8 +18,9 @@
  */
 
 use get_if_addrs;
+use get_if_addrs::IfAddr;
 use std::cmp::Ordering;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 pub fn interface_exists(iface: &str) -> bool {
     if iface == "localhost" {
@@ -106,6 +107,76 @@ pub fn get_local_addr(ipv6: bool) -> Option<String> {
     None
 }
 
+/// Port used unless told otherwise.
+pub const DEFAULT_PORT: u16 = 8888;
+
+/// Parses the --port option, returning the port to try first and the
+/// ones to fall back to if it's taken. That's a single port, a range of
+/// them ("8888-8899") or "auto", which tries the default port and lets
+/// the OS pick a free one if that's taken.
+pub fn parse_port(s: &str) -> Result<(u16, Vec<u16>), String> {
+    let invalid = || format!("Invalid port \"{}\"", s);
+    if s == "auto" {
+        return Ok((DEFAULT_PORT, vec![0]));
+    }
+    let mut bounds = s.splitn(2, '-');
+    let first = bounds
+        .next()
+        .unwrap()
+        .trim()
+        .parse::<u16>()
+        .map_err(|_| invalid())?;
+    match bounds.next() {
+        Some(last) => {
+            let last = last.trim().parse::<u16>().map_err(|_| invalid())?;
+            if last < first {
+                return Err(invalid());
+            }
+            Ok((first, (first..last).map(|p| p + 1).collect()))
+        }
+        None => Ok((first, Vec::new())),
+    }
+}
+
+/// Parses a listen address, either an IP address alone, which gets the
+/// default port, or one with a port ("0.0.0.0:9000", "[::]:9000").
+pub fn parse_listen_addr(addr: &str, default_port: u16) -> Result<SocketAddr, String> {
+    if let Ok(a) = addr.parse::<SocketAddr>() {
+        return Ok(a);
+    }
+    match addr
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => Ok(SocketAddr::new(ip, default_port)),
+        Err(_) => Err(format!("Invalid listen address \"{}\"", addr)),
+    }
+}
+
+/// Addresses clients can reach a server bound to `addr` at. For "0.0.0.0"
+/// and "::" that's every interface address of the same family, leaving
+/// out IPv6 link-local ones, which can't be used in a URL without a zone.
+pub fn reachable_addrs(addr: SocketAddr) -> Vec<SocketAddr> {
+    if !addr.ip().is_unspecified() {
+        return vec![addr];
+    }
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for i in get_if_addrs::get_if_addrs().unwrap() {
+        let reachable = match (i.ip(), addr.ip()) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => true,
+            (IpAddr::V6(v6), IpAddr::V6(_)) => !v6.is_unicast_link_local(),
+            _ => false,
+        };
+        if reachable {
+            addrs.push(SocketAddr::new(i.ip(), addr.port()));
+        }
+    }
+    if addrs.is_empty() {
+        addrs.push(addr);
+    }
+    addrs
+}
+
 pub fn get_all_addrs() -> Vec<String> {
     let ifaces = get_if_addrs::get_if_addrs().unwrap();
     let mut addrs: Vec<String> = Vec::new();
@@ -114,3 +185,22 @@ pub fn get_all_addrs() -> Vec<String> {
     }
     addrs
 }
+
+/// Returns the IPv4 broadcast address of every non-loopback interface.
+pub fn get_broadcast_addrs() -> Vec<Ipv4Addr> {
+    let ifaces = get_if_addrs::get_if_addrs().unwrap();
+    let mut addrs: Vec<Ipv4Addr> = Vec::new();
+    for i in ifaces {
+        if i.is_loopback() {
+            continue;
+        }
+        if let IfAddr::V4(v4) = i.addr {
+            if let Some(b) = v4.broadcast {
+                if !addrs.contains(&b) {
+                    addrs.push(b);
+                }
+            }
+        }
+    }
+    addrs
+}
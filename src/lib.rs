@@ -0,0 +1,87 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![feature(ip)]
+
+extern crate chrono;
+extern crate get_if_addrs;
+extern crate getopts;
+pub extern crate hyper;
+pub extern crate hyper_router;
+extern crate md5;
+extern crate mime;
+extern crate multipart;
+extern crate mustache;
+extern crate notify;
+extern crate percent_encoding;
+extern crate pulldown_cmark;
+extern crate regex;
+extern crate rustc_serialize;
+extern crate sha2;
+extern crate url;
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! println_cond {
+    ($b:expr, $($p:expr),+) => (
+        if $b {println!($($p,)+)})
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! printerr_cond {
+    ($b:expr, $($p:expr),+) => (
+        if $b {
+            use std::io::Write;
+            match writeln!(&mut ::std::io::stderr(), $($p,)+) {
+                Ok(_)  => {},
+                Err(e) => {panic!("Write to stderr failed: {}", e);}
+            };
+        })
+}
+
+pub mod api;
+pub mod audit;
+mod checksum;
+pub mod client;
+pub mod directory;
+pub mod discovery;
+mod format;
+pub mod index;
+pub mod ip;
+mod listener;
+pub mod locale;
+mod markdown;
+pub mod routes;
+pub mod server;
+mod session;
+pub mod shutdown;
+mod snippet;
+mod static_r;
+mod theme;
+pub mod trash;
+pub mod versions;
+
+pub use directory::Directory;
+pub use hyper_router::Route;
+pub use routes::{HandlerState, SavedFile};
+pub use server::{ServerBuilder, ServerHandle};
+pub use shutdown::ShutdownPolicy;
+
+pub const VERSION: &'static str = "0.2.0";
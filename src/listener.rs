@@ -0,0 +1,203 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use hyper::header::{Connection, Expect, Headers};
+use hyper::http;
+use hyper::net::{HttpStream, NetworkStream};
+use hyper::server::{Handler, Request, Response};
+use hyper::status::StatusCode;
+use hyper::version::HttpVersion;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How many threads accept and serve connections on each address.
+pub const THREADS: usize = 8;
+
+/// How long a connection is kept open while waiting for a request.
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+struct Shared {
+    socket: Mutex<Option<Arc<TcpListener>>>,
+    closed: AtomicBool,
+    addr: SocketAddr,
+}
+
+/// A listening socket that can be closed. hyper's own server keeps its
+/// accept threads, and with them the socket, until the process exits, so
+/// connections are accepted here and only the requests are left to hyper.
+#[derive(Clone)]
+pub struct Listener(Arc<Shared>);
+
+/// The threads answering the connections made to a listener.
+pub struct Serving {
+    listener: Listener,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Listener {
+    pub fn bind(addr: &str) -> io::Result<Listener> {
+        let socket = TcpListener::bind(addr)?;
+        let addr = socket.local_addr()?;
+        Ok(Listener(Arc::new(Shared {
+            socket: Mutex::new(Some(Arc::new(socket))),
+            closed: AtomicBool::new(false),
+            addr: addr,
+        })))
+    }
+
+    /// Stops accepting connections. The socket is closed once every
+    /// accept thread has let go of it, which they are made to do by
+    /// connecting to it. Should that fail, for instance because the
+    /// address went away, the port stays taken until the process exits.
+    pub fn close(&self) {
+        if self.0.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.0.socket.lock().unwrap().take();
+        let mut wake = self.0.addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        for _ in 0..THREADS {
+            let _ = TcpStream::connect(wake);
+        }
+    }
+
+    /// Waits for the next connection. Returns `None` once the listener
+    /// is closed.
+    fn accept(&self) -> Option<io::Result<TcpStream>> {
+        let socket = self.0.socket.lock().unwrap().clone()?;
+        let accepted = socket.accept();
+        drop(socket);
+        if self.0.closed.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(accepted.map(|(stream, _)| stream))
+    }
+}
+
+impl Serving {
+    /// Answers connections to `listener` with `handler` until the
+    /// listener is closed.
+    pub fn start<H: Handler + 'static>(listener: Listener, handler: H) -> Serving {
+        let handler = Arc::new(handler);
+        let threads = (0..THREADS)
+            .map(|_| {
+                let listener = listener.clone();
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    while let Some(accepted) = listener.accept() {
+                        if let Ok(stream) = accepted {
+                            // A handler that panics only loses its own
+                            // connection, not the thread.
+                            let _ =
+                                panic::catch_unwind(AssertUnwindSafe(|| serve(&*handler, stream)));
+                        }
+                    }
+                })
+            })
+            .collect();
+        Serving { listener, threads }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.listener.0.addr
+    }
+
+    /// Stops accepting connections, so that the address can be bound
+    /// again. Each thread ends once it's done with the connection it's
+    /// on, if any.
+    pub fn close(&self) {
+        self.listener.close();
+    }
+
+    /// Blocks until every thread has ended, which they only do once the
+    /// listener is closed.
+    pub fn join(self) {
+        for t in self.threads {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Answers the requests made over a connection one after the other, the
+/// way hyper's own server does.
+fn serve<H: Handler>(handler: &H, stream: TcpStream) {
+    let addr = match stream.peer_addr() {
+        Ok(a) => a,
+        Err(_) => return,
+    };
+    let mut input = match stream.try_clone() {
+        Ok(s) => HttpStream(s),
+        Err(_) => return,
+    };
+    let mut rdr = BufReader::new(&mut input as &mut dyn NetworkStream);
+    let mut wrt = BufWriter::new(stream);
+    loop {
+        // The wait for a request is limited, reading its body isn't.
+        if wrt.get_ref().set_read_timeout(Some(KEEP_ALIVE)).is_err() {
+            break;
+        }
+        let req = match Request::new(&mut rdr, addr) {
+            Ok(r) => r,
+            Err(_) => break,
+        };
+        if wrt.get_ref().set_read_timeout(None).is_err() {
+            break;
+        }
+        if !send_continue(handler, &req, &mut wrt) {
+            break;
+        }
+        let version = req.version;
+        let keep_alive = http::should_keep_alive(version, &req.headers);
+        let mut headers = Headers::new();
+        if !keep_alive {
+            headers.set(Connection::close());
+        }
+        {
+            let mut res = Response::new(&mut wrt, &mut headers);
+            res.version = version;
+            handler.handle(req, res);
+        }
+        if !keep_alive || !http::should_keep_alive(version, &headers) {
+            break;
+        }
+    }
+    let _ = wrt.get_ref().shutdown(Shutdown::Both);
+}
+
+/// Tells a client that waits before sending the body whether to go
+/// ahead. Returns false if it shouldn't.
+fn send_continue<H: Handler, W: Write>(handler: &H, req: &Request, out: &mut W) -> bool {
+    if req.version != HttpVersion::Http11 || req.headers.get() != Some(&Expect::Continue) {
+        return true;
+    }
+    let status = handler.check_continue((&req.method, &req.uri, &req.headers));
+    let sent = write!(out, "{} {}\r\n\r\n", HttpVersion::Http11, status).and_then(|_| out.flush());
+    sent.is_ok() && status == StatusCode::Continue
+}
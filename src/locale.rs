@@ -0,0 +1,262 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use chrono::datetime::DateTime;
+use chrono::naive::datetime::NaiveDateTime;
+use chrono::offset::utc::UTC;
+use chrono::offset::TimeZone;
+use chrono::{Datelike, Timelike};
+use std::time::{Duration, UNIX_EPOCH};
+
+use directory;
+
+/// Units file sizes are shown in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Units {
+    /// Powers of 1000: kB, MB, GB.
+    Si,
+    /// Powers of 1024: KiB, MiB, GiB.
+    Iec,
+}
+
+impl Units {
+    pub fn parse(s: &str) -> Result<Units, String> {
+        match s {
+            "si" => Ok(Units::Si),
+            "iec" => Ok(Units::Iec),
+            _ => Err(format!("Invalid units \"{}\", expected si or iec", s)),
+        }
+    }
+}
+
+/// Formats a size with one decimal, "1.5 kB" or "1.4 KiB".
+pub fn size(bytes: u64, units: Units) -> String {
+    let (base, names) = match units {
+        Units::Si => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB"]),
+        Units::Iec => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB"]),
+    };
+    if (bytes as f64) < base {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // Rounding can carry over into the next unit, 999.96 kB is 1.0 MB.
+    while (value * 10.0).round() / 10.0 >= base && unit < names.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, names[unit])
+}
+
+/// Languages month and weekday names are available in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Lang {
+    En,
+    De,
+    Es,
+    Fr,
+    It,
+    Nl,
+    Pl,
+    Pt,
+    Sr,
+}
+
+impl Lang {
+    fn from_tag(tag: &str) -> Option<Lang> {
+        let primary = tag.split('-').next().unwrap_or("").trim().to_lowercase();
+        match primary.as_str() {
+            "en" => Some(Lang::En),
+            "de" => Some(Lang::De),
+            "es" => Some(Lang::Es),
+            "fr" => Some(Lang::Fr),
+            "it" => Some(Lang::It),
+            "nl" => Some(Lang::Nl),
+            "pl" => Some(Lang::Pl),
+            "pt" => Some(Lang::Pt),
+            "sr" => Some(Lang::Sr),
+            _ => None,
+        }
+    }
+
+    /// Picks the language the client prefers most out of the ones we
+    /// have, falling back to English.
+    pub fn from_accept_language(header: &str) -> Lang {
+        let mut best: Option<(Lang, f32)> = None;
+        for range in header.split(',') {
+            let mut parts = range.split(';');
+            let lang = match Lang::from_tag(parts.next().unwrap()) {
+                Some(l) => l,
+                None => continue,
+            };
+            let quality = parts
+                .filter_map(|p| {
+                    let p = p.trim();
+                    if p.starts_with("q=") {
+                        p[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+                best = Some((lang, quality));
+            }
+        }
+        best.map_or(Lang::En, |(l, _)| l)
+    }
+
+    /// The language tag of the page.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Lang::En => "en",
+            Lang::De => "de",
+            Lang::Es => "es",
+            Lang::Fr => "fr",
+            Lang::It => "it",
+            Lang::Nl => "nl",
+            Lang::Pl => "pl",
+            Lang::Pt => "pt",
+            Lang::Sr => "sr",
+        }
+    }
+
+    fn months(&self) -> [&'static str; 12] {
+        match *self {
+            Lang::En => [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+            ],
+            Lang::De => [
+                "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+            ],
+            Lang::Es => [
+                "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic",
+            ],
+            Lang::Fr => [
+                "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+                "nov.", "déc.",
+            ],
+            Lang::It => [
+                "gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic",
+            ],
+            Lang::Nl => [
+                "jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec",
+            ],
+            Lang::Pl => [
+                "sty", "lut", "mar", "kwi", "maj", "cze", "lip", "sie", "wrz", "paź", "lis", "gru",
+            ],
+            Lang::Pt => [
+                "jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez",
+            ],
+            Lang::Sr => [
+                "jan", "feb", "mar", "apr", "maj", "jun", "jul", "avg", "sep", "okt", "nov", "dec",
+            ],
+        }
+    }
+
+    /// Starting with Monday.
+    fn weekdays(&self) -> [&'static str; 7] {
+        match *self {
+            Lang::En => ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+            Lang::De => ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+            Lang::Es => ["lun", "mar", "mié", "jue", "vie", "sáb", "dom"],
+            Lang::Fr => ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+            Lang::It => ["lun", "mar", "mer", "gio", "ven", "sab", "dom"],
+            Lang::Nl => ["ma", "di", "wo", "do", "vr", "za", "zo"],
+            Lang::Pl => ["pon", "wt", "śr", "czw", "pt", "sob", "niedz"],
+            Lang::Pt => ["seg", "ter", "qua", "qui", "sex", "sáb", "dom"],
+            Lang::Sr => ["pon", "uto", "sre", "čet", "pet", "sub", "ned"],
+        }
+    }
+}
+
+/// How sizes and dates are shown on the page.
+#[derive(Clone, Copy)]
+pub struct Style {
+    pub units: Units,
+    pub utc: bool,
+    pub lang: Lang,
+}
+
+impl Style {
+    pub fn size(&self, bytes: u64) -> String {
+        size(bytes, self.units)
+    }
+
+    pub fn timestamp(&self, secs: u64) -> String {
+        timestamp(secs, self.utc, self.lang)
+    }
+}
+
+/// Formats a date the way it's shown on the page. English keeps the
+/// month first, the other languages put the day first.
+pub fn date<Tz: TimeZone>(date: &DateTime<Tz>, lang: Lang) -> String {
+    let weekday = lang.weekdays()[date.weekday().num_days_from_monday() as usize];
+    let month = lang.months()[date.month0() as usize];
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        date.hour(),
+        date.minute(),
+        date.second()
+    );
+    match lang {
+        Lang::En => format!(
+            "{}, {} {:02} {}  {}",
+            weekday,
+            month,
+            date.day(),
+            date.year(),
+            time
+        ),
+        _ => format!(
+            "{}, {} {} {}  {}",
+            weekday,
+            date.day(),
+            month,
+            date.year(),
+            time
+        ),
+    }
+}
+
+/// Formats seconds since the unix epoch in local time, or in UTC.
+pub fn timestamp(secs: u64, utc: bool, lang: Lang) -> String {
+    if utc {
+        let ndt = NaiveDateTime::from_timestamp(secs as i64, 0);
+        return format!("{} UTC", date(&UTC.from_utc_datetime(&ndt), lang));
+    }
+    match directory::local_date(UNIX_EPOCH + Duration::from_secs(secs)) {
+        Some(d) => date(&d, lang),
+        None => "n/a".to_string(),
+    }
+}
+
+/// RFC 3339 time with the offset of the local timezone, or in UTC.
+pub fn rfc3339(secs: u64, utc: bool) -> String {
+    if utc {
+        return NaiveDateTime::from_timestamp(secs as i64, 0)
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+    }
+    match directory::local_date(UNIX_EPOCH + Duration::from_secs(secs)) {
+        Some(d) => d.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        None => rfc3339(secs, true),
+    }
+}
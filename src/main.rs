@@ -17,66 +17,57 @@
  * along with na.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-#![feature(ip)]
-
-extern crate chrono;
-extern crate get_if_addrs;
+#[macro_use]
+extern crate na;
 extern crate getopts;
-extern crate hyper;
-extern crate hyper_router;
-extern crate mime;
-extern crate multipart;
-extern crate mustache;
-extern crate percent_encoding;
-extern crate regex;
-extern crate rustc_serialize;
-extern crate url;
-
-macro_rules! println_cond {
-    ($b:expr, $($p:expr),+) => (
-        if $b {println!($($p,)+)})
-}
-
-macro_rules! printerr_cond {
-    ($b:expr, $($p:expr),+) => (
-        if $b {
-            use std::io::Write;
-            match writeln!(&mut ::std::io::stderr(), $($p,)+) {
-                Ok(_)  => {},
-                Err(e) => {panic!("Write to stderr failed: {}", e);}
-            };
-        })
-}
-
-mod directory;
-mod format;
-mod ip;
-mod month;
-mod routes;
-mod static_r;
 
-use directory::Directory;
-use getopts::Options;
-use hyper::server::Server;
-use hyper_router::{Route, RouterBuilder};
-
-use routes::{
-    DeleteHandler, FileDownloadHandler, FileUploadHandler, HandlerState, IndexHandler, JSONHandler,
-    ListHandler, StaticResourceHandler,
-};
-
-use static_r::Resource;
+use getopts::{Matches, Options};
+use na::directory::{Directory, Stream, SymlinkPolicy};
+use na::locale::Units;
+use na::shutdown::{self, ShutdownPolicy};
+use na::versions::{self, VersionPolicy};
+use na::{audit, client, discovery, index, ip, trash, ServerBuilder, VERSION};
 
 use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
-
-const VERSION: &'static str = "0.2.0";
+use std::process;
+use std::time::Duration;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program_name = args[0].clone();
 
+    if args.len() > 1 {
+        match args[1].as_str() {
+            "ls" | "get" | "send" => {
+                if let Err(e) = client::run(&program_name, &args[1], &args[2..]) {
+                    printerr_cond!(true, "Error: {}", e);
+                    process::exit(1);
+                }
+                return;
+            }
+            "audit" => {
+                if let Err(e) = audit::run(&program_name, &args[2..]) {
+                    printerr_cond!(true, "Error: {}", e);
+                    process::exit(1);
+                }
+                return;
+            }
+            "discover" => {
+                if let Err(e) = discovery::run(&program_name, &args[2..]) {
+                    printerr_cond!(true, "Error: {}", e);
+                    process::exit(1);
+                }
+                return;
+            }
+            _ => {}
+        }
+    }
+
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help message and exit");
     opts.optopt(
@@ -93,7 +84,25 @@ fn main() {
         "enables file deletions trough DELETE requests",
     );
     opts.optflag("u", "disable-upload", "disables file uploads");
-    opts.optopt("p", "port", "port number", "NUMBER");
+    opts.optopt(
+        "p",
+        "port",
+        "port number, a range of ports to try in turn (eg. \"8888-8899\") \
+                 or \"auto\" to fall back to a free one if 8888 is taken",
+        "PORT",
+    );
+    opts.optopt(
+        "",
+        "port-file",
+        "write the port the server is listening on to FILE",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "startup-json",
+        "print a line of JSON with the addresses and URLs of the server \
+                 to stderr once it's running",
+    );
     opts.optflag(
         "o",
         "overwrite-file",
@@ -116,11 +125,157 @@ fn main() {
         "INTERFACE",
     );
     opts.optflag("6", "ipv6", "prefer IPv6 if available");
+    opts.optmulti(
+        "",
+        "listen",
+        "address to listen on, with or without a port (eg. \"0.0.0.0\", \
+                 \"::\", \"[::1]:9000\"). Can be given more than once, \
+                 replaces -i and -6",
+        "ADDRESS",
+    );
     opts.optflag(
         "l",
         "list-interfaces",
         "print a list of available network interfaces and exit",
     );
+    opts.optopt(
+        "",
+        "timeout",
+        "stop the server after the given time (eg. \"90s\", \"30m\", \"1h\")",
+        "DURATION",
+    );
+    opts.optopt(
+        "",
+        "idle-timeout",
+        "stop the server after it has been idle for the given time",
+        "DURATION",
+    );
+    opts.optopt(
+        "",
+        "max-downloads",
+        "stop the server after the given number of completed downloads",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "name",
+        "file name under which standard input is shared (default \"stdin\")",
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "spool",
+        "buffer standard input to a temporary file so that it \
+                  can be downloaded more than once",
+    );
+    opts.optflag(
+        "",
+        "dropbox",
+        "receive-only mode, clients can upload files but only \
+                  see the files they uploaded themselves",
+    );
+    opts.optopt(
+        "",
+        "inbox",
+        "store uploaded files in PATH instead of the served \
+                 directory (implies --dropbox)",
+        "PATH",
+    );
+    opts.optopt(
+        "t",
+        "token",
+        "require clients to present this access token",
+        "TOKEN",
+    );
+    opts.optflag(
+        "",
+        "no-announce",
+        "don't announce this instance to \"na discover\" on the local network",
+    );
+    opts.optopt(
+        "",
+        "label",
+        "name under which this instance is announced (default \
+                 is the name of the served directory)",
+        "LABEL",
+    );
+    opts.optopt(
+        "",
+        "beacon-port",
+        "UDP port that announcements are sent to (default 8887)",
+        "PORT",
+    );
+    opts.optopt(
+        "",
+        "theme-dir",
+        "directory with an index.html, stylesheets, scripts or \
+                 other assets that replace the built-in ones",
+        "PATH",
+    );
+    opts.optflag(
+        "",
+        "versions",
+        "keep older versions of files that uploads replace, instead \
+                  of saving uploads under a new name",
+    );
+    opts.optopt(
+        "",
+        "keep-versions",
+        "how many older versions of a file to keep (default 10)",
+        "NUMBER",
+    );
+    opts.optopt(
+        "",
+        "max-version-age",
+        "drop older versions after this long",
+        "DURATION",
+    );
+    opts.optflag(
+        "",
+        "hard-delete",
+        "delete files right away instead of moving them to the trash",
+    );
+    opts.optopt(
+        "",
+        "trash-retention",
+        "how long deleted files are kept in the trash (default 7d)",
+        "DURATION",
+    );
+    opts.optflag(
+        "",
+        "no-index",
+        "read the served directory for every request instead of keeping \
+                 its listing in memory",
+    );
+    opts.optopt(
+        "",
+        "rescan-interval",
+        "how often the served directory is read again if changes to it \
+                 can't be watched (default 30s)",
+        "DURATION",
+    );
+    opts.optopt(
+        "",
+        "units",
+        "units file sizes are shown in: \"si\" (kB, MB) or \"iec\" \
+                 (KiB, MiB), default si",
+        "UNITS",
+    );
+    opts.optflag("", "utc", "show times in UTC instead of the local timezone");
+    opts.optopt(
+        "",
+        "audit-log",
+        "append a JSON line for every upload, download, delete, restore and purge to FILE",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "symlinks",
+        "which symbolic links to serve: \"follow\" all of them, only \
+                 those pointing \"within\" the served directory (default) \
+                 or \"never\"",
+        "POLICY",
+    );
     opts.optflag("v", "verbose", "verbose output");
     opts.optflag("", "version", "print version info");
 
@@ -147,7 +302,8 @@ fn main() {
         return;
     }
 
-    let current_dir = match options.opt_str("d") {
+    let dropbox = options.opt_present("dropbox") || options.opt_present("inbox");
+    let current_dir = match options.opt_str("inbox").or(options.opt_str("d")) {
         Some(d) => {
             let mut dir = PathBuf::new();
             dir.push(d);
@@ -155,107 +311,458 @@ fn main() {
         }
         None => env::current_dir().unwrap(),
     };
-    let port = match options.opt_str("p") {
-        Some(p) => p,
-        None => "8888".to_string(),
-    };
-    let address = match options.opt_str("i") {
-        Some(a) => {
-            if ip::interface_exists(&a) {
-                match ip::get_iface_addr(&a, options.opt_present("6")) {
-                    Ok(i) => Some(i),
-                    Err(e) => {
-                        println!("{}", e);
-                        None
-                    }
-                }
-            } else {
+    if options.opt_present("inbox") {
+        if let Err(e) = fs::create_dir_all(&current_dir) {
+            printerr_cond!(true, "Error: Unable to create inbox, {}", e);
+            return;
+        }
+    }
+    let theme_dir = match options.opt_str("theme-dir") {
+        Some(t) => {
+            let dir = PathBuf::from(t);
+            if !dir.is_dir() {
                 printerr_cond!(
                     true,
-                    "Error: Specified interface \
-                                      \"{}\" does not exist!",
-                    a
+                    "Error: Theme directory \"{}\" does not exist!",
+                    dir.display()
                 );
                 return;
             }
+            Some(dir)
         }
-        None => ip::get_local_addr(options.opt_present("6")),
+        None => None,
     };
-    let addr = match address {
-        Some(a) => a,
-        None => {
-            printerr_cond!(true, "Error: No active network interfaces found!");
-            return;
+    let (port, fallback_ports) = match options.opt_str("p") {
+        Some(p) => match ip::parse_port(&p) {
+            Ok(p) => p,
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        },
+        None => (ip::DEFAULT_PORT, Vec::new()),
+    };
+    let mut listen: Vec<SocketAddr> = Vec::new();
+    for a in options.opt_strs("listen") {
+        match ip::parse_listen_addr(&a, port) {
+            Ok(a) => listen.push(a),
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
         }
+    }
+    if listen.is_empty() {
+        match local_listen_addr(&options, port) {
+            Ok(a) => listen.push(a),
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        }
+    }
+
+    let units = match options.opt_str("units") {
+        Some(u) => match Units::parse(&u) {
+            Ok(u) => u,
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        },
+        None => Units::Si,
+    };
+    let index_rescan = match options.opt_str("rescan-interval") {
+        Some(t) => match shutdown::parse_duration(&t) {
+            Ok(d) => d,
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        },
+        None => Duration::from_secs(index::DEFAULT_RESCAN),
+    };
+    let trash_retention = match options.opt_str("trash-retention") {
+        Some(t) => match shutdown::parse_duration(&t) {
+            Ok(d) => d,
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        },
+        None => Duration::from_secs(trash::DEFAULT_RETENTION),
+    };
+
+    let version_policy = if options.opt_present("versions") {
+        Some(VersionPolicy {
+            keep: match options.opt_str("keep-versions") {
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) => Some(n),
+                    Err(_) => {
+                        printerr_cond!(true, "Error: Invalid number of versions \"{}\"", n);
+                        return;
+                    }
+                },
+                None => Some(versions::DEFAULT_KEEP),
+            },
+            max_age: match options.opt_str("max-version-age") {
+                Some(t) => match shutdown::parse_duration(&t) {
+                    Ok(d) => Some(d),
+                    Err(e) => {
+                        printerr_cond!(true, "Error: {}", e);
+                        return;
+                    }
+                },
+                None => None,
+            },
+        })
+    } else {
+        None
+    };
+
+    let policy = ShutdownPolicy {
+        deadline: match options.opt_str("timeout") {
+            Some(t) => match shutdown::parse_timeout(&t) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    printerr_cond!(true, "Error: {}", e);
+                    return;
+                }
+            },
+            None => None,
+        },
+        idle: match options.opt_str("idle-timeout") {
+            Some(t) => match shutdown::parse_timeout(&t) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    printerr_cond!(true, "Error: {}", e);
+                    return;
+                }
+            },
+            None => None,
+        },
+        max_downloads: match options.opt_str("max-downloads") {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    printerr_cond!(true, "Error: Invalid download count \"{}\"", n);
+                    return;
+                }
+            },
+            None => None,
+        },
     };
 
     let str_path = current_dir.to_str().unwrap().clone().to_string();
-    let directory = Directory::new(current_dir);
-    let static_res = Resource::new();
+    let mut directory = Directory::new(current_dir);
+    if let Some(s) = options.opt_str("symlinks") {
+        match SymlinkPolicy::parse(&s) {
+            Ok(p) => directory.symlinks = p,
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        }
+    }
+    let sharing_files = !options.free.is_empty();
+    // Kept until na stops, when the spooled standard input is removed.
+    let mut _spool = None;
 
-    let addr_and_port = format!("{}:{}", addr, port);
-    let srv = match Server::http(&*addr_and_port) {
-        Ok(s) => s,
+    if sharing_files {
+        let stdin_name = match options.opt_str("name") {
+            Some(n) => n,
+            None => "stdin".to_string(),
+        };
+        match shared_files(&options.free, stdin_name, options.opt_present("spool")) {
+            Ok((files, stream, spool)) => {
+                directory.files = Some(files);
+                directory.stream = stream;
+                _spool = spool;
+            }
+            Err(e) => {
+                printerr_cond!(true, "Error: {}", e);
+                return;
+            }
+        }
+    }
+    let mut builder = ServerBuilder::new(directory).bind(&listen[0].to_string());
+    for a in &listen[1..] {
+        builder = builder.also_bind(&a.to_string());
+    }
+    let handle = match builder
+        .fallback_ports(fallback_ports)
+        .verbose(options.opt_present("v"))
+        .enable_delete(options.opt_present("r"))
+        .enable_upload(!options.opt_present("u") && !sharing_files)
+        .show_directory(options.opt_present("s"))
+        .overwrite(options.opt_present("o"))
+        .dropbox(dropbox)
+        .token(options.opt_str("t"))
+        .max_downloads(policy.max_downloads)
+        .theme_dir(theme_dir)
+        .hard_delete(options.opt_present("hard-delete"))
+        .trash_retention(trash_retention)
+        .versions(version_policy)
+        .audit_log(options.opt_str("audit-log").map(PathBuf::from))
+        .index(if options.opt_present("no-index") {
+            None
+        } else {
+            Some(index_rescan)
+        })
+        .units(units)
+        .utc(options.opt_present("utc"))
+        .start()
+    {
+        Ok(h) => h,
         Err(e) => {
-            printerr_cond!(
-                true,
-                "Error: Unable to start na at ({}), {}",
-                addr_and_port,
-                e
-            );
+            printerr_cond!(true, "Error: {}", e);
             return;
         }
     };
+    let hs = handle.state().clone();
 
-    if options.opt_present("6") {
+    let token_query = match hs.token {
+        Some(_) => format!("/{}", hs.token_query()),
+        None => "".to_string(),
+    };
+    let urls: Vec<String> = handle
+        .addrs()
+        .into_iter()
+        .flat_map(ip::reachable_addrs)
+        .map(|a| format!("http://{}", a))
+        .collect();
+    if urls.len() == 1 {
         println!(
-            "Serving contents of {} at http://[{}]:{}",
-            str_path, addr, port
+            "Serving contents of {} at {}{}",
+            str_path, urls[0], token_query
         );
     } else {
-        println!(
-            "Serving contents of {} at http://{}",
-            str_path, addr_and_port
-        );
+        println!("Serving contents of {} at", str_path);
+        for u in &urls {
+            println!("    {}{}", u, token_query);
+        }
+    }
+    let url = urls[0].clone();
+    if options.opt_present("startup-json") {
+        printerr_cond!(true, "{}", handle.startup_json(&urls));
+    }
+    if let Some(f) = options.opt_str("port-file") {
+        if let Err(e) = fs::write(&f, format!("{}\n", handle.addr().port())) {
+            printerr_cond!(true, "Error: Unable to write port file {}, {}", f, e);
+        }
+    }
+
+    if !options.opt_present("no-announce") {
+        let beacon_port = match options.opt_str("beacon-port") {
+            Some(p) => match p.parse::<u16>() {
+                Ok(p) => p,
+                Err(_) => {
+                    printerr_cond!(true, "Error: Invalid port \"{}\"", p);
+                    return;
+                }
+            },
+            None => discovery::BEACON_PORT,
+        };
+        let label = match options.opt_str("label") {
+            Some(l) => l,
+            None => match hs.directory.root.file_name() {
+                Some(n) => n.to_string_lossy().to_string(),
+                None => str_path.clone(),
+            },
+        };
+        let mut capabilities: Vec<String> = Vec::new();
+        if !hs.no_upload {
+            capabilities.push("upload".to_string());
+        }
+        if hs.delete {
+            capabilities.push("delete".to_string());
+        }
+        if hs.dropbox {
+            capabilities.push("dropbox".to_string());
+        }
+        if hs.token.is_some() {
+            capabilities.push("token".to_string());
+        }
+        let beacon = discovery::Beacon::new(label, url.clone(), VERSION, capabilities);
+        if let Err(e) = discovery::announce(beacon, beacon_port) {
+            printerr_cond!(options.opt_present("v"), "Error: {}", e);
+        }
+    }
+
+    if policy.deadline.is_none() && policy.idle.is_none() && policy.max_downloads.is_none() {
+        handle.join();
+        return;
+    }
+    let reason = handle.run_until(&policy);
+
+    println!("Shutting down, {}.", reason);
+    println!("{}", hs.activity.summary());
+}
+
+/// Picks the address of the interface given with -i, or the first
+/// local one.
+fn local_listen_addr(options: &Matches, port: u16) -> Result<SocketAddr, String> {
+    let ipv6 = options.opt_present("6");
+    let addr = match options.opt_str("i") {
+        Some(i) => {
+            if !ip::interface_exists(&i) {
+                return Err(format!("Specified interface \"{}\" does not exist!", i));
+            }
+            ip::get_iface_addr(&i, ipv6)?
+        }
+        None => match ip::get_local_addr(ipv6) {
+            Some(a) => a,
+            None => return Err("No active network interfaces found!".to_string()),
+        },
+    };
+    ip::parse_listen_addr(&addr, port)
+}
+
+/// Temporary directory standard input is spooled to. It's removed when
+/// this is dropped, or when na is interrupted.
+struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    /// Copies standard input to a file called `name` and returns where
+    /// it is.
+    fn create(name: &str) -> Result<(Spool, PathBuf), String> {
+        let spool = Spool {
+            dir: env::temp_dir().join(format!("na-{}", process::id())),
+        };
+        if let Err(e) = fs::create_dir_all(&spool.dir) {
+            return Err(format!("Unable to create spool directory, {}", e));
+        }
+        let path = spool.dir.join(name);
+        interrupt::remove_on_exit(&path, &spool.dir);
+        let mut spool_file = match File::create(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Unable to create spool file, {}", e)),
+        };
+        let stdin = io::stdin();
+        if let Err(e) = io::copy(&mut stdin.lock(), &mut spool_file) {
+            return Err(format!("Unable to spool standard input, {}", e));
+        }
+        Ok((spool, path))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Ctrl-C ends na without unwinding, so the spool is removed from the
+/// signal handler, with nothing but calls that are safe to make there.
+#[cfg(unix)]
+mod interrupt {
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+        fn raise(sig: c_int) -> c_int;
+        fn unlink(path: *const c_char) -> c_int;
+        fn rmdir(path: *const c_char) -> c_int;
+    }
+
+    static FILE: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+    static DIR: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+
+    pub fn remove_on_exit(file: &Path, dir: &Path) {
+        let c_path = |p: &Path| CString::new(p.as_os_str().as_bytes()).ok();
+        let (file, dir) = match (c_path(file), c_path(dir)) {
+            (Some(f), Some(d)) => (f, d),
+            _ => return,
+        };
+        // Both are needed for as long as the process runs.
+        FILE.store(file.into_raw(), Ordering::SeqCst);
+        DIR.store(dir.into_raw(), Ordering::SeqCst);
+        let handler = on_signal as extern "C" fn(c_int) as usize;
+        unsafe {
+            signal(SIGINT, handler);
+            signal(SIGTERM, handler);
+        }
+    }
+
+    extern "C" fn on_signal(sig: c_int) {
+        unsafe {
+            unlink(FILE.load(Ordering::SeqCst));
+            rmdir(DIR.load(Ordering::SeqCst));
+            // Ends the process the way the signal would have.
+            signal(sig, SIG_DFL);
+            raise(sig);
+        }
     }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+    use std::path::Path;
 
-    let hs = Arc::new(HandlerState {
-        directory: directory,
-        resource: static_res,
-        verbose: options.opt_present("v"),
-        delete: options.opt_present("r"),
-        no_upload: options.opt_present("u"),
-        showdir: options.opt_present("s"),
-        overwrite: options.opt_present("o"),
-        path: str_path.clone(),
-    });
-
-    let index_handler = IndexHandler(hs.clone());
-    let file_dl_handler = FileDownloadHandler(hs.clone());
-    let file_ul_handler = FileUploadHandler(hs.clone());
-    let resource_handler = StaticResourceHandler(hs.clone());
-    let json_handler = JSONHandler(hs.clone());
-    let delete_handler = DeleteHandler(hs.clone());
-    let list_handler = ListHandler(hs.clone());
-
-    let router = RouterBuilder::new()
-        .add(Route::get(r"(/|/index.html)").using(index_handler))
-        .add(Route::post(r"(/|/index.html)").using(file_ul_handler))
-        .add(Route::delete(r"/files/[^/]+$").using(delete_handler))
-        .add(Route::get(r"/files/[^/]+$").using(file_dl_handler))
-        .add(Route::get(r"/resource/[^/]+$").using(resource_handler))
-        .add(Route::get(r"/json").using(json_handler))
-        .add(Route::get(r"/list").using(list_handler))
-        .set_handler_404(routes::handler_404)
-        .set_handler_405(routes::handler_405)
-        .set_handler_500(routes::handler_500)
-        .build();
-
-    let _ = srv.handle(router);
+    pub fn remove_on_exit(_: &Path, _: &Path) {}
+}
+
+/// Resolves the files given on the command line. A "-" shares standard
+/// input, either streamed to the first downloader or spooled to a file.
+fn shared_files(
+    args: &[String],
+    stdin_name: String,
+    spool: bool,
+) -> Result<(Vec<PathBuf>, Option<Stream>, Option<Spool>), String> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut stream = None;
+    let mut spooled = None;
+
+    for a in args {
+        if a == "-" {
+            if stream.is_some() || files.iter().any(|f| f.ends_with(&stdin_name)) {
+                return Err("Standard input can only be shared once".to_string());
+            }
+            if spool {
+                let (s, path) = Spool::create(&stdin_name)?;
+                spooled = Some(s);
+                files.push(path);
+            } else {
+                stream = Some(Stream::new(stdin_name.clone()));
+            }
+            continue;
+        }
+        let path = PathBuf::from(a);
+        if !path.is_file() {
+            return Err(format!("\"{}\" is not a file", a));
+        }
+        let duplicate = files.iter().any(|f| f.file_name() == path.file_name())
+            || stream.as_ref().map(|s: &Stream| path.ends_with(&s.name)) == Some(true);
+        if duplicate {
+            return Err(format!("More than one file is named \"{}\"", a));
+        }
+        files.push(path);
+    }
+    Ok((files, stream, spooled))
 }
 
 fn print_help(name: &str, opts: Options) {
-    let brief = format!("Usage: {} [OPTIONS]", name);
+    let brief = format!(
+        "Usage: {0} [OPTIONS] [FILE...]\n       \
+         {0} ls|get|send [OPTIONS] URL ...\n       \
+         {0} discover [OPTIONS]\n       \
+         {0} audit [OPTIONS] LOG\n\n\
+         Shares the given files, or the served directory if none are given.\n\
+         A FILE of \"-\" shares standard input. The ls, get and send commands\n\
+         talk to another running na, see \"{0} get --help\".",
+        name
+    );
     println!("{}", opts.usage(&brief));
 }
 
@@ -0,0 +1,106 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Largest file that is rendered, bigger ones are only offered for download.
+pub const MAX_RENDER_SIZE: u64 = 1024 * 1024;
+
+/// File names that are shown below the file table, in order of preference.
+const README_NAMES: [&'static str; 4] = ["readme.md", "readme.markdown", "readme.txt", "readme"];
+
+pub fn is_markdown(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".md") || name.ends_with(".markdown")
+}
+
+/// Picks the README out of a list of file names, if there is one.
+pub fn find_readme<'a, I>(names: I) -> Option<&'a str>
+where
+    I: Iterator<Item = &'a str> + Clone,
+{
+    for readme in README_NAMES.iter() {
+        if let Some(n) = names.clone().find(|n| n.to_lowercase() == *readme) {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Renders Markdown files to HTML and anything else as preformatted text.
+pub fn render(name: &str, text: &str) -> String {
+    if !is_markdown(name) {
+        return format!("<pre>{}</pre>", escape(text));
+    }
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let mut out = String::new();
+    html::push_html(&mut out, Parser::new_ext(text, options).map(sanitize));
+    out
+}
+
+/// The rendered text ends up inside our own page, so raw HTML is shown
+/// as text instead of being passed through, and links may only point to
+/// the web, mail or other shared files.
+fn sanitize(event: Event) -> Event {
+    match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        e => e,
+    }
+}
+
+/// Relative links are taken to refer to other shared files.
+fn safe_url(url: CowStr) -> CowStr {
+    let lower = url.to_lowercase();
+    if lower.starts_with("http://")
+        || lower.starts_with("https://")
+        || lower.starts_with("mailto:")
+        || lower.starts_with('#')
+        || lower.starts_with('/')
+    {
+        return url;
+    }
+    if lower.contains(':') {
+        return CowStr::Borrowed("#");
+    }
+    CowStr::Boxed(format!("/files/{}", url).into_boxed_str())
+}
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
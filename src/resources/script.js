@@ -66,27 +66,107 @@ function sortBySize() {
 
 function deleteResource(event) {
     var http = new XMLHttpRequest();
+    var t = event.target;
+    var res = t.attributes.getNamedItem("res").value;
     http.onreadystatechange = function() {
         if (http.readyState == 4 && http.status == 200) {
+            var restore = http.getResponseHeader("Na-Restore");
+            if (restore) {
+                var name = decodeURIComponent(res.split("/").pop());
+                sessionStorage.setItem("na-undo", JSON.stringify({name: name, url: restore}));
+            }
             location.reload(true);
         }
     }
-    var t = event.target;
-    http.open("DELETE",
-              t.attributes.getNamedItem("res").value,
-              true);
+    http.open("DELETE", res, true);
+    http.send(null);
+}
 
+function sendAndReload(method, url) {
+    var http = new XMLHttpRequest();
+    http.onreadystatechange = function() {
+        if (http.readyState == 4) {
+            location.reload(true);
+        }
+    }
+    http.open(method, url, true);
     http.send(null);
 }
 
+function restoreResource(event) {
+    sendAndReload("POST", event.target.attributes.getNamedItem("res").value);
+}
+
+function purgeResource(event) {
+    sendAndReload("DELETE", event.target.attributes.getNamedItem("res").value);
+}
+
+function showUndo() {
+    var undo = sessionStorage.getItem("na-undo");
+    if (!undo) {
+        return;
+    }
+    sessionStorage.removeItem("na-undo");
+    undo = JSON.parse(undo);
+
+    var toast = document.createElement("div");
+    var button = document.createElement("button");
+    toast.id = "undo-toast";
+    toast.appendChild(document.createTextNode("Deleted " + undo.name + " "));
+    button.textContent = "undo";
+    button.onclick = function () {
+        sendAndReload("POST", undo.url);
+    };
+    toast.appendChild(button);
+    document.body.appendChild(toast);
+    setTimeout(function () {
+        if (toast.parentNode) {
+            toast.parentNode.removeChild(toast);
+        }
+    }, 10000);
+}
+
+function copySnippet(event) {
+    var id = event.target.attributes.getNamedItem("snippet").value;
+    var text = document.getElementById("snippet-" + id).textContent;
+    var area = document.createElement("textarea");
+
+    area.value = text;
+    document.body.appendChild(area);
+    area.select();
+    document.execCommand("copy");
+    document.body.removeChild(area);
+    event.target.textContent = "copied";
+}
+
+function onClass(name, handler) {
+    var elements = document.getElementsByClassName(name);
+
+    for (var i = 0; i < elements.length; i++) {
+        elements[i].onclick = handler;
+    }
+}
+
 window.onload = function () {
+    onClass("restore-button", restoreResource);
+    onClass("purge-button", purgeResource);
+    if (!document.getElementById("file-table")) {
+        return;
+    }
     document.getElementById("hname").onclick = sortByName;
     document.getElementById("hsize").onclick = sortBySize;
     document.getElementById("hmodified").onclick = sortByDate;
+    showUndo();
 
     var elements = document.getElementsByClassName('delete-button');
 
     for (var i = 0; i < elements.length; i++) {
         elements[i].onclick = deleteResource;
     }
+
+    var copyButtons = document.getElementsByClassName('copy-button');
+
+    for (var i = 0; i < copyButtons.length; i++) {
+        copyButtons[i].onclick = copySnippet;
+    }
 }
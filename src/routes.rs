@@ -17,60 +17,412 @@
  * along with na.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use percent_encoding::percent_decode;
+use percent_encoding::{percent_decode, utf8_percent_encode, NON_ALPHANUMERIC};
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::ToHex;
 use rustc_serialize::json;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::Write;
-use std::ops::Deref;
+use std::io::{Seek, SeekFrom};
 use std::str;
 use std::sync::Arc;
 
 use hyper::header::Charset;
+use hyper::header::Connection;
 use hyper::header::ContentDisposition;
 use hyper::header::ContentLength;
+use hyper::header::ContentType;
 use hyper::header::DispositionParam;
 use hyper::header::DispositionType;
 use hyper::header::Location;
+use hyper::method::Method;
+use hyper::mime::{Attr, Mime, SubLevel, TopLevel, Value};
 use hyper::server::{Handler, Request, Response};
 use hyper::status::StatusCode;
 use hyper::uri::RequestUri;
 
-use directory::Directory;
+use api;
+use audit::{self, AuditLog, Event};
+use checksum::{self, Expected, HashingWriter};
+use directory::{self, Directory, FileMeta};
 use format;
-use static_r::Resource;
+use locale::{Lang, Style, Units};
+use markdown;
+use session::{self, Sessions};
+use shutdown::{self, Activity};
+use snippet::{Snippets, MAX_SNIPPET_SIZE};
+use theme::{self, Theme};
+use trash::Trash;
+use versions::Versions;
 
 use multipart::server::{Multipart, MultipartData};
 
 pub struct HandlerState {
     pub verbose: bool,
     pub directory: Directory,
-    pub resource: Resource,
+    pub theme: Theme,
     pub delete: bool,
     pub no_upload: bool,
     pub path: String,
     pub showdir: bool,
     pub overwrite: bool,
+    pub activity: Activity,
+    pub dropbox: bool,
+    pub sessions: Sessions,
+    pub snippets: Snippets,
+    pub token: Option<String>,
+    pub hooks: Hooks,
+    /// Where deleted files go, unless they are deleted for good.
+    pub trash: Option<Trash>,
+    /// Keeps older revisions of replaced files in versioned mode.
+    pub versions: Option<Versions>,
+    /// Persistent record of uploads, downloads, deletions, restores and
+    /// purges.
+    pub audit: Option<AuditLog>,
+    /// Units sizes are shown in on the page.
+    pub units: Units,
+}
+
+/// Callbacks run after a file was uploaded, downloaded or deleted.
+pub struct Hooks {
+    pub upload: Option<Box<dyn Fn(&SavedFile) + Send + Sync>>,
+    pub download: Option<Box<dyn Fn(&str) + Send + Sync>>,
+    pub delete: Option<Box<dyn Fn(&str) + Send + Sync>>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks {
+            upload: None,
+            download: None,
+            delete: None,
+        }
+    }
+}
+
+impl HandlerState {
+    /// Returns the files the client is allowed to see. In drop box mode
+    /// that is only what the client itself has uploaded.
+    pub fn visible_resources(&self, session: &Option<String>) -> Vec<FileMeta> {
+        let resources = self.directory.list_available_resources();
+        if !self.dropbox {
+            return resources;
+        }
+        match *session {
+            Some(ref id) => resources
+                .into_iter()
+                .filter(|r| self.owns(id, &r.name))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the session uploaded the file that's called `name` now.
+    fn owns(&self, session: &str, name: &str) -> bool {
+        match self.directory.file_id(name) {
+            Some(file) => self.sessions.owns(session, name, file),
+            None => false,
+        }
+    }
+
+    /// Lets the session see the file it saved as `name`.
+    fn claim(&self, session: &str, name: &str) {
+        if let Some(file) = self.directory.file_id(name) {
+            self.sessions.record(session, name, file);
+        }
+    }
+
+    /// Returns the file called `name` if the client is allowed to see it.
+    pub fn find_resource(&self, session: &Option<String>, name: &str) -> Option<FileMeta> {
+        if self.dropbox {
+            match *session {
+                Some(ref id) if self.owns(id, name) => {}
+                _ => return None,
+            }
+        }
+        self.directory.lookup(name)
+    }
+
+    /// Streams an upload into a partial file, checks it against the
+    /// expected digests and moves it into place under the sanitized
+    /// `name`. With `exclusive` it fails with `AlreadyExists` instead of
+    /// touching a file that's already there. Returns the saved name, the
+    /// size and the sha-256 digest.
+    pub fn save_upload<R: Read>(
+        &self,
+        upload: &mut R,
+        name: &str,
+        expected: &[Expected],
+        exclusive: bool,
+    ) -> Result<(String, u64, Vec<u8>), UploadError> {
+        let name = directory::sanitize_name(name);
+        let (partial, file) = self.directory.create_partial().map_err(UploadError::Io)?;
+        let mut writer = HashingWriter::new(file, expected);
+        let size = match io::copy(upload, &mut writer) {
+            Ok(s) => s,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(UploadError::Io(e));
+            }
+        };
+        let (file, digests) = writer.finish();
+        drop(file);
+        if let Err(e) = digests.verify(expected) {
+            let _ = fs::remove_file(&partial);
+            return Err(UploadError::Mismatch(e));
+        }
+        let committed = match self.versioning() {
+            _ if exclusive => {
+                // Nothing is replaced, so there's nothing to archive.
+                let committed = self.directory.commit_partial_exclusive(&partial, &name);
+                committed.map(|_| name.clone())
+            }
+            Some(versions) => {
+                let _guard = versions.lock();
+                // The file stays in place until the upload is renamed
+                // over it, and its revision is the old inode.
+                let committed = versions
+                    .archive(&self.directory.root.join(&name), &name)
+                    .and_then(|archived| {
+                        let committed = self.directory.commit_partial(&partial, &name, true);
+                        if committed.is_err() {
+                            versions.unarchive(&name, archived);
+                        }
+                        committed
+                    });
+                versions.prune(&name);
+                committed
+            }
+            None => self
+                .directory
+                .commit_partial(&partial, &name, self.replaces_files()),
+        };
+        match committed {
+            Ok(saved) => Ok((saved, size, digests.sha256)),
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                Err(UploadError::Io(e))
+            }
+        }
+    }
+
+    /// Versions are kept for uploads that replace a file. A drop box
+    /// never replaces anything, so it doesn't get any.
+    pub fn versioning(&self) -> Option<&Versions> {
+        match self.versions {
+            Some(ref v) if !self.dropbox => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Whether an upload replaces the file of the same name rather than
+    /// being saved under a free one. Versioned uploads do, the old file is
+    /// kept as a version.
+    pub fn replaces_files(&self) -> bool {
+        self.versioning().is_some() || (self.overwrite && !self.dropbox)
+    }
+
+    /// How sizes and dates are shown to the client that sent `req`.
+    pub fn style(&self, req: &Request) -> Style {
+        Style {
+            units: self.units,
+            utc: self.directory.utc,
+            lang: raw_header(req, "Accept-Language")
+                .map_or(Lang::En, |l| Lang::from_accept_language(&l)),
+        }
+    }
+
+    /// The trash, if clients may browse it, restore from it and empty it.
+    /// It holds what everyone deleted, which a drop box mustn't give away.
+    pub fn browsable_trash(&self) -> Option<&Trash> {
+        match self.trash {
+            Some(ref t) if self.delete && !self.dropbox => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Snippets are seen by everyone, so a drop box, where clients only
+    /// see what they uploaded themselves, doesn't have any.
+    pub fn shared_snippets(&self) -> Option<&Snippets> {
+        if self.dropbox {
+            None
+        } else {
+            Some(&self.snippets)
+        }
+    }
+
+    /// Query string that carries the access token, for links that are
+    /// followed by programs which can't send it in a header.
+    pub fn token_query(&self) -> String {
+        match self.token {
+            Some(ref t) => format!("?token={}", utf8_percent_encode(t, NON_ALPHANUMERIC)),
+            None => String::new(),
+        }
+    }
+
+    /// Appends the event to the audit log, if there is one.
+    pub fn audit(&self, event: Event) {
+        if let Some(ref log) = self.audit {
+            if let Err(e) = log.record(&event) {
+                printerr_cond!(true, "Error: Couldn't write to the audit log, {}", e);
+            }
+        }
+    }
+
+    /// Renders the README among the given files, if there is one.
+    pub fn readme(&self, resources: &Vec<FileMeta>) -> Option<String> {
+        let name = markdown::find_readme(resources.iter().map(|r| r.name.as_str()))?;
+        self.render_file(name)
+    }
+
+    fn render_file(&self, name: &str) -> Option<String> {
+        if let Some(ref stdin) = self.directory.stream {
+            if stdin.name == name {
+                return None;
+            }
+        }
+        let path = self.directory.resolve(name)?;
+        let mut contents = Vec::new();
+        match File::open(&*path) {
+            Ok(f) => f
+                .take(markdown::MAX_RENDER_SIZE)
+                .read_to_end(&mut contents)
+                .ok()?,
+            Err(_) => return None,
+        };
+        Some(markdown::render(name, &String::from_utf8_lossy(&contents)))
+    }
 }
 
 pub struct FileDownloadHandler(pub Arc<HandlerState>);
 pub struct FileUploadHandler(pub Arc<HandlerState>);
+pub struct FilePutHandler(pub Arc<HandlerState>);
 pub struct IndexHandler(pub Arc<HandlerState>);
 pub struct StaticResourceHandler(pub Arc<HandlerState>);
 pub struct JSONHandler(pub Arc<HandlerState>);
 pub struct DeleteHandler(pub Arc<HandlerState>);
 pub struct ListHandler(pub Arc<HandlerState>);
+pub struct SnippetHandler(pub Arc<HandlerState>);
+pub struct SnippetListHandler(pub Arc<HandlerState>);
+pub struct SnippetUploadHandler(pub Arc<HandlerState>);
+pub struct SnippetDeleteHandler(pub Arc<HandlerState>);
+pub struct TrashHandler(pub Arc<HandlerState>);
+pub struct TrashRestoreHandler(pub Arc<HandlerState>);
+pub struct TrashPurgeHandler(pub Arc<HandlerState>);
+pub struct VersionsHandler(pub Arc<HandlerState>);
+pub struct VersionRestoreHandler(pub Arc<HandlerState>);
+pub struct HelpHandler(pub Arc<HandlerState>);
+pub struct ApiListHandler(pub Arc<HandlerState>);
+pub struct ApiFileHandler(pub Arc<HandlerState>);
+pub struct ApiInfoHandler(pub Arc<HandlerState>);
+pub struct ApiSpecHandler(pub Arc<HandlerState>);
 
-pub fn handler_400(mut res: Response, msg: &str) {
-    {
-        let stat: &mut StatusCode = res.status_mut();
-        *stat = StatusCode::BadRequest;
+/// Wraps the router so that every request is counted as activity, checked
+/// against the access token and refused once the server has started
+/// shutting down.
+///
+/// The router is shared between the servers for every listen address.
+pub struct TrackingHandler<H: Handler>(pub Arc<HandlerState>, pub Arc<H>);
+
+/// An error as API clients get it, wrapped in `{"error": ...}`.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct ApiError {
+    pub status: u16,
+    /// Machine readable name of the error, such as "not_found".
+    pub code: String,
+    pub message: String,
+    /// The uploaded file the error is about, if any.
+    pub file: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, message: &str) -> ApiError {
+        ApiError {
+            status: status.to_u16(),
+            code: code.to_string(),
+            message: message.to_string(),
+            file: None,
+        }
     }
-    res.send(msg.as_bytes()).unwrap();
 }
 
-pub fn handler_404(_: Request, mut res: Response) {
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct ErrorReply {
+    pub error: ApiError,
+}
+
+/// Answer to a multi-file upload some of whose files couldn't be saved.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct UploadReport {
+    pub saved: Vec<SavedFile>,
+    pub failed: Vec<ApiError>,
+}
+
+/// Whether the request comes from a program that wants errors and
+/// results as JSON, either by asking for it or by using `/api`.
+pub fn is_api(req: &Request) -> bool {
+    request_path(req).map_or(false, |p| p.starts_with("/api/"))
+        || Page::from_request(req) == Page::Json
+}
+
+/// Sends an error as JSON to API clients and as a line of text to
+/// everyone else.
+fn send_error(mut res: Response, api: bool, error: ApiError) {
+    let status = StatusCode::from_u16(error.status);
+    *res.status_mut() = status;
+    if api {
+        res.headers_mut().set(application_json());
+        let body = json::encode(&ErrorReply { error: error }).unwrap();
+        res.send(format!("{}\n", body).as_bytes()).unwrap();
+    } else {
+        let msg = format!(
+            "{} ({}). {}\n",
+            status.canonical_reason().unwrap_or("Error"),
+            error.status,
+            error.message
+        );
+        res.send(msg.as_bytes()).unwrap();
+    }
+}
+
+/// Answers a HEAD request for standard input, which can't say how long
+/// it is, or that it's gone once it was downloaded.
+fn send_head(mut res: Response, taken: bool) {
+    if taken {
+        *res.status_mut() = StatusCode::NotFound;
+    }
+    res.headers_mut().set(ContentLength(0));
+    let _ = res.start().and_then(|s| s.end());
+}
+
+/// Answers a successful request that has nothing to say, such as a
+/// delete made by an API client.
+fn send_no_content(mut res: Response) {
+    *res.status_mut() = StatusCode::NoContent;
+    res.send(b"").unwrap();
+}
+
+pub fn handler_400(res: Response, api: bool, msg: &str) {
+    send_error(
+        res,
+        api,
+        ApiError::new(StatusCode::BadRequest, "bad_request", msg),
+    );
+}
+
+pub fn handler_404(req: Request, mut res: Response) {
+    if is_api(&req) {
+        let msg = "There's nothing here.";
+        send_error(
+            res,
+            true,
+            ApiError::new(StatusCode::NotFound, "not_found", msg),
+        );
+        return;
+    }
     {
         let stat: &mut StatusCode = res.status_mut();
         *stat = StatusCode::NotFound;
@@ -81,36 +433,44 @@ pub fn handler_404(_: Request, mut res: Response) {
     res.send(msg.as_bytes()).unwrap();
 }
 
-pub fn handler_405_delete(_: Request, mut res: Response) {
-    {
-        let stat: &mut StatusCode = res.status_mut();
-        *stat = StatusCode::MethodNotAllowed;
-    }
-    let msg = "Method Not Allowed (405). DELETE is not enabled for \
-               /files resources.\n";
-    res.send(msg.as_bytes()).unwrap();
+fn method_not_allowed(req: Request, res: Response, msg: &str) {
+    let error = ApiError::new(StatusCode::MethodNotAllowed, "method_not_allowed", msg);
+    send_error(res, is_api(&req), error);
 }
 
-pub fn handler_405_post(_: Request, mut res: Response) {
-    {
-        let stat: &mut StatusCode = res.status_mut();
-        *stat = StatusCode::MethodNotAllowed;
-    }
-    let msg = "Method Not Allowed (405). POST is not enabled for \
-               /files resources.\n";
-    res.send(msg.as_bytes()).unwrap();
+pub fn handler_405_delete(req: Request, res: Response) {
+    method_not_allowed(req, res, "DELETE is not enabled for /files resources.");
 }
 
-pub fn handler_405(_: Request, mut res: Response) {
-    {
-        let stat: &mut StatusCode = res.status_mut();
-        *stat = StatusCode::MethodNotAllowed;
-    }
-    let msg = "Method Not Allowed (405)\n";
-    res.send(msg.as_bytes()).unwrap();
+pub fn handler_405_post(req: Request, res: Response) {
+    method_not_allowed(req, res, "POST is not enabled for /files resources.");
+}
+
+pub fn handler_405_put(req: Request, res: Response) {
+    method_not_allowed(req, res, "PUT is not enabled for /files resources.");
+}
+
+pub fn handler_412(res: Response, api: bool, msg: &str) {
+    let error = ApiError::new(StatusCode::PreconditionFailed, "precondition_failed", msg);
+    send_error(res, api, error);
 }
 
-pub fn handler_500(_: Request, mut res: Response) {
+pub fn handler_413(res: Response, api: bool, msg: &str) {
+    let error = ApiError::new(StatusCode::PayloadTooLarge, "too_large", msg);
+    send_error(res, api, error);
+}
+
+pub fn handler_405(req: Request, res: Response) {
+    method_not_allowed(req, res, "The method isn't allowed here.");
+}
+
+pub fn handler_500(req: Request, mut res: Response) {
+    if is_api(&req) {
+        let msg = "Something went wrong on the server.";
+        let error = ApiError::new(StatusCode::InternalServerError, "internal_error", msg);
+        send_error(res, true, error);
+        return;
+    }
     {
         let stat: &mut StatusCode = res.status_mut();
         *stat = StatusCode::InternalServerError;
@@ -122,32 +482,704 @@ pub fn handler_500(_: Request, mut res: Response) {
     res.send(msg.as_bytes()).unwrap();
 }
 
-impl Handler for IndexHandler {
-    fn handle(&self, _: Request, res: Response) {
-        let resource = self.0.directory.list_available_resources();
-        let rendered = format::html(
-            self.0.resource.r.get("/resource/index.html").unwrap(),
-            &resource,
-            self.0.delete,
-            self.0.showdir,
-            self.0.no_upload,
-            self.0.path.clone(),
-        );
-        res.send(rendered.as_bytes()).unwrap();
+pub fn handler_401(res: Response, api: bool) {
+    let msg = "This na instance requires an access token, open the link you \
+               were given or pass it as \"Authorization: Bearer <token>\".";
+    send_error(
+        res,
+        api,
+        ApiError::new(StatusCode::Unauthorized, "unauthorized", msg),
+    );
+}
+
+pub fn handler_503(res: Response, api: bool, msg: &str) {
+    let error = ApiError::new(StatusCode::ServiceUnavailable, "unavailable", msg);
+    send_error(res, api, error);
+}
+
+fn text_plain() -> ContentType {
+    ContentType(Mime(
+        TopLevel::Text,
+        SubLevel::Plain,
+        vec![(Attr::Charset, Value::Utf8)],
+    ))
+}
+
+fn application_json() -> ContentType {
+    ContentType(Mime(
+        TopLevel::Application,
+        SubLevel::Json,
+        vec![(Attr::Charset, Value::Utf8)],
+    ))
+}
+
+/// The percent-decoded path of the request, without the query string.
+fn request_path(req: &Request) -> Option<String> {
+    let path = match req.uri {
+        RequestUri::AbsolutePath(ref path) => path,
+        _ => return None,
+    };
+    let path = path.split('?').next().unwrap();
+    percent_decode(path.as_bytes())
+        .decode_utf8()
+        .ok()
+        .map(|p| p.to_string())
+}
+
+/// Returns the value of `key` in the query string of the request.
+fn query_param(req: &Request, key: &str) -> Option<String> {
+    let path = match req.uri {
+        RequestUri::AbsolutePath(ref path) => path,
+        _ => return None,
+    };
+    let query = match path.find('?') {
+        Some(i) => &path[i + 1..],
+        None => return None,
+    };
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() == Some(key) {
+            let value = kv.next().unwrap_or("");
+            return percent_decode(value.as_bytes())
+                .decode_utf8()
+                .ok()
+                .map(|v| v.to_string());
+        }
+    }
+    None
+}
+
+fn raw_header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|v| v.first())
+        .and_then(|v| str::from_utf8(v).ok())
+        .map(|v| v.to_string())
+}
+
+/// The file a request is for, the segment that follows `/files/` as in
+/// `/files/<name>` and `/api/v1/files/<name>/content`.
+fn file_target(path: &str) -> String {
+    path.split('/')
+        .skip_while(|s| *s != "files")
+        .nth(1)
+        .unwrap_or("")
+        .to_string()
+}
+
+/// The address the client reached us at, for pages read by programs
+/// that don't know it.
+fn request_base(req: &Request) -> String {
+    format!(
+        "http://{}",
+        raw_header(req, "Host").unwrap_or("localhost".to_string())
+    )
+}
+
+/// Forms of the index page.
+#[derive(Clone, Copy, PartialEq)]
+enum Page {
+    Html,
+    Text,
+    Json,
+}
+
+impl Page {
+    /// Goes by the Accept header first. Command line clients accept
+    /// anything, so they're told apart by their User-Agent.
+    fn from_request(req: &Request) -> Page {
+        if let Some(accept) = raw_header(req, "Accept") {
+            for media_type in accept.split(',') {
+                match media_type.split(';').next().unwrap().trim() {
+                    "text/html" | "application/xhtml+xml" => return Page::Html,
+                    "application/json" => return Page::Json,
+                    "text/plain" => return Page::Text,
+                    _ => continue,
+                }
+            }
+        }
+        let agent = raw_header(req, "User-Agent").unwrap_or_default();
+        let agent = agent.to_lowercase();
+        if ["curl/", "wget/", "httpie/"]
+            .iter()
+            .any(|a| agent.starts_with(a))
+        {
+            Page::Text
+        } else {
+            Page::Html
+        }
+    }
+}
+
+/// Formats the list of files is available in, asked for either by path
+/// or through the Accept header.
+#[derive(Clone, Copy, PartialEq)]
+enum Listing {
+    Text,
+    Json,
+    Csv,
+    Xml,
+    Atom,
+    M3u,
+}
+
+impl Listing {
+    fn from_path(path: &str) -> Option<Listing> {
+        match path {
+            "/list.csv" => Some(Listing::Csv),
+            "/list.xml" => Some(Listing::Xml),
+            "/feed.atom" => Some(Listing::Atom),
+            "/playlist.m3u8" => Some(Listing::M3u),
+            _ => None,
+        }
+    }
+
+    /// Picks the first of the accepted types that's one of ours. Browsers
+    /// put text/html first, so they keep getting the plain list.
+    fn from_accept(accept: &str) -> Option<Listing> {
+        for media_type in accept.split(',') {
+            let media_type = media_type.split(';').next().unwrap().trim();
+            let listing = match media_type {
+                "text/plain" | "text/html" => Listing::Text,
+                "application/json" => Listing::Json,
+                "text/csv" => Listing::Csv,
+                "application/xml" | "text/xml" => Listing::Xml,
+                "application/atom+xml" => Listing::Atom,
+                "audio/x-mpegurl" | "audio/mpegurl" | "application/vnd.apple.mpegurl" => {
+                    Listing::M3u
+                }
+                _ => continue,
+            };
+            return Some(listing);
+        }
+        None
+    }
+}
+
+/// Returns the user name and password sent with basic authentication.
+fn basic_auth(req: &Request) -> Option<(String, String)> {
+    let header = raw_header(req, "Authorization")?;
+    if !header.starts_with("Basic ") {
+        return None;
+    }
+    let decoded = header["Basic ".len()..].trim().from_base64().ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    let user = parts.next()?.to_string();
+    Some((user, parts.next().unwrap_or("").to_string()))
+}
+
+/// The user a request was made by, as far as na knows. That's the name
+/// given with basic authentication, as long as the password was the
+/// access token. Without one anybody can claim any name.
+fn request_user(req: &Request, token: &Option<String>) -> Option<String> {
+    let (user, password) = basic_auth(req)?;
+    match *token {
+        Some(ref t) if *t == password && !user.is_empty() => Some(user),
+        _ => None,
+    }
+}
+
+/// Parses a single `Range: bytes=...` request into an inclusive byte range.
+/// Anything we don't understand is ignored and the whole file is sent.
+fn byte_range(req: &Request, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let value = match raw_header(req, "Range") {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if !value.starts_with("bytes=") || value.contains(',') {
+        return Ok(None);
+    }
+    let mut bounds = value["bytes=".len()..].splitn(2, '-');
+    let first = bounds.next().unwrap_or("").trim();
+    let last = bounds.next().unwrap_or("").trim();
+
+    let range = if first.is_empty() {
+        match last.parse::<u64>() {
+            Ok(n) if n > 0 && len > 0 => (len.saturating_sub(n), len - 1),
+            _ => return Err(()),
+        }
+    } else {
+        let start = match first.parse::<u64>() {
+            Ok(s) => s,
+            Err(_) => return Ok(None),
+        };
+        let end = match last.parse::<u64>() {
+            Ok(e) => e.min(len.saturating_sub(1)),
+            Err(_) => len.saturating_sub(1),
+        };
+        (start, end)
+    };
+    if range.0 >= len || range.0 > range.1 {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+fn wants_digest(req: &Request) -> bool {
+    match raw_header(req, "Want-Repr-Digest") {
+        Some(v) => v.to_lowercase().contains("sha-256"),
+        None => false,
+    }
+}
+
+/// The trash entry id in `/trash/<id>` or `/trash/<id>/restore`.
+fn trash_id(req: &Request) -> Option<String> {
+    let path = request_path(req)?;
+    path.split('/').nth(2).map(|id| id.to_string())
+}
+
+fn snippet_id(req: &Request) -> Option<usize> {
+    match req.uri {
+        RequestUri::AbsolutePath(ref path) => {
+            let path = path.split('?').next().unwrap();
+            path.split('/').last().and_then(|id| id.parse().ok())
+        }
+        _ => None,
+    }
+}
+
+impl Handler for TrashHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let trash = match self.0.browsable_trash() {
+            Some(t) => t,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        let style = self.0.style(&req);
+        let entries = trash.list(&style);
+        let wants_json =
+            raw_header(&req, "Accept").map_or(false, |a| a.contains("application/json"));
+        if wants_json {
+            res.headers_mut().set(application_json());
+            res.send(json::encode(&entries).unwrap().as_bytes())
+                .unwrap();
+            return;
+        }
+        let rendered = format::trash(&self.0.theme.template("trash.html"), &entries, &style);
+        res.headers_mut()
+            .set(ContentType(theme::content_type(".html")));
+        res.send(rendered.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for TrashRestoreHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let trash = match self.0.browsable_trash() {
+            Some(t) => t,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        let id = match trash_id(&req) {
+            Some(id) => id,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        match trash.restore(&id, &self.0.directory) {
+            Ok(restored) => {
+                println_cond!(
+                    self.0.verbose,
+                    "Restored {} as {}",
+                    restored.name,
+                    restored.restored_name
+                );
+                let size = self
+                    .0
+                    .directory
+                    .resolve(&restored.restored_name)
+                    .and_then(|p| fs::metadata(p).ok())
+                    .map_or(0, |m| m.len());
+                self.0.audit(Event::new(
+                    audit::RESTORE,
+                    &restored.restored_name,
+                    size,
+                    &req.remote_addr.to_string(),
+                    request_user(&req, &self.0.token),
+                ));
+                res.headers_mut().set(application_json());
+                res.send(format!("{}\n", json::encode(&restored).unwrap()).as_bytes())
+                    .unwrap();
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => handler_404(req, res),
+            Err(e) => {
+                printerr_cond!(self.0.verbose, "Error: Couldn't restore {}, {}", id, e);
+                handler_500(req, res);
+            }
+        }
+    }
+}
+
+impl Handler for TrashPurgeHandler {
+    fn handle(&self, req: Request, res: Response) {
+        let trash = match self.0.browsable_trash() {
+            Some(t) => t,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        match trash_id(&req) {
+            Some(id) => match trash.purge(&id) {
+                Ok(purged) => {
+                    println_cond!(self.0.verbose, "Purged {} from the trash", id);
+                    self.0.audit(Event::new(
+                        audit::PURGE,
+                        &purged.name,
+                        purged.size,
+                        &req.remote_addr.to_string(),
+                        request_user(&req, &self.0.token),
+                    ));
+                    if is_api(&req) {
+                        send_no_content(res);
+                        return;
+                    }
+                    res.send(b"Purged\n").unwrap();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => handler_404(req, res),
+                Err(e) => {
+                    printerr_cond!(self.0.verbose, "Error: Couldn't purge {}, {}", id, e);
+                    handler_500(req, res);
+                }
+            },
+            None => {
+                let purged = trash.purge_all();
+                println_cond!(self.0.verbose, "Emptied the trash, {} files", purged.len());
+                let remote = req.remote_addr.to_string();
+                let user = request_user(&req, &self.0.token);
+                for p in &purged {
+                    self.0.audit(Event::new(
+                        audit::PURGE,
+                        &p.name,
+                        p.size,
+                        &remote,
+                        user.clone(),
+                    ));
+                }
+                if is_api(&req) {
+                    send_no_content(res);
+                    return;
+                }
+                res.send(format!("Purged {} files\n", purged.len()).as_bytes())
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// The file name in `/versions/<name>` and the version number in
+/// `/versions/<name>/<n>`.
+fn version_target(req: &Request) -> Option<(String, Option<u64>)> {
+    let path = request_path(req)?;
+    let mut segments = path.split('/').skip(2);
+    let name = segments.next()?.to_string();
+    Some((name, segments.next().and_then(|n| n.parse().ok())))
+}
+
+impl Handler for VersionsHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let session = self.0.sessions.from_request(&req);
+        let versions = self.0.versioning();
+        let name = version_target(&req).map(|(name, _)| name);
+        let path = name
+            .as_ref()
+            .and_then(|n| self.0.find_resource(&session, n))
+            .and_then(|r| self.0.directory.resolve(&r.name));
+        let (versions, name, path) = match (versions, name, path) {
+            (Some(v), Some(n), Some(p)) => (v, n, p),
+            _ => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        let style = self.0.style(&req);
+        let revisions = versions.list(&name, &path, &style);
+        let wants_json =
+            raw_header(&req, "Accept").map_or(false, |a| a.contains("application/json"));
+        if wants_json {
+            res.headers_mut().set(application_json());
+            res.send(json::encode(&revisions).unwrap().as_bytes())
+                .unwrap();
+            return;
+        }
+        let rendered = format::versions(
+            &self.0.theme.template("versions.html"),
+            &name,
+            &revisions,
+            !self.0.no_upload,
+            &style,
+        );
+        res.headers_mut()
+            .set(ContentType(theme::content_type(".html")));
+        res.send(rendered.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for VersionRestoreHandler {
+    fn handle(&self, req: Request, res: Response) {
+        if self.0.no_upload {
+            handler_405_post(req, res);
+            return;
+        }
+        let session = self.0.sessions.from_request(&req);
+        let (name, version) = match version_target(&req) {
+            Some((name, Some(version))) => (name, version),
+            _ => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        let path = self
+            .0
+            .find_resource(&session, &name)
+            .and_then(|r| self.0.directory.resolve(&r.name));
+        let (versions, path) = match (self.0.versioning(), path) {
+            (Some(v), Some(p)) => (v, p),
+            _ => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        match versions.restore(&name, version, &path) {
+            Ok(_) => {
+                self.0.directory.touch(&name);
+                println_cond!(self.0.verbose, "Restored version {} of {}", version, name);
+                self.0.audit(Event::new(
+                    audit::RESTORE,
+                    &name,
+                    fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                    &req.remote_addr.to_string(),
+                    request_user(&req, &self.0.token),
+                ));
+                res.send(format!("Restored version {} of {}\n", version, name).as_bytes())
+                    .unwrap();
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => handler_404(req, res),
+            Err(e) => {
+                printerr_cond!(
+                    self.0.verbose,
+                    "Error: Couldn't restore version {} of {}, {}",
+                    version,
+                    name,
+                    e
+                );
+                handler_500(req, res);
+            }
+        }
+    }
+}
+
+impl<H: Handler> Handler for TrackingHandler<H> {
+    fn handle(&self, req: Request, mut res: Response) {
+        if self.0.activity.is_closing() {
+            // Ends kept-alive connections, which would otherwise keep
+            // coming back here.
+            res.headers_mut().set(Connection::close());
+            handler_503(res, is_api(&req), "Na is shutting down.");
+            return;
+        }
+        if let Some(ref token) = self.0.token {
+            let bearer = raw_header(&req, "Authorization")
+                .map(|a| a.trim_start_matches("Bearer ").trim().to_string());
+            let cookie = session::cookie_value(&req, "na_token");
+            let query = query_param(&req, "token");
+            // Lets clients name themselves, as in "curl -u alice:<token>".
+            let password = basic_auth(&req).map(|(_, p)| p);
+
+            if query.as_ref() == Some(token) {
+                res.headers_mut().set_raw(
+                    "Set-Cookie",
+                    vec![format!("na_token={}; Path=/; HttpOnly", token).into_bytes()],
+                );
+            } else if bearer.as_ref() != Some(token)
+                && cookie.as_ref() != Some(token)
+                && password.as_ref() != Some(token)
+            {
+                handler_401(res, is_api(&req));
+                return;
+            }
+        }
+        // Whatever is written after the headers of an answer to HEAD would
+        // be taken for the start of the next answer.
+        if req.method == Method::Head {
+            res.headers_mut().set(Connection::close());
+        }
+        let _in_flight = self.0.activity.begin();
+        self.1.handle(req, res);
+    }
+}
+
+impl Handler for IndexHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let session = self.0.sessions.from_request(&req);
+        let resource = self.0.visible_resources(&session);
+        if self.0.dropbox && session.is_none() {
+            let id = self.0.sessions.create();
+            res.headers_mut()
+                .set_raw("Set-Cookie", vec![Sessions::cookie(&id)]);
+        }
+        res.headers_mut()
+            .set_raw("Vary", vec![b"Accept, User-Agent".to_vec()]);
+        let style = self.0.style(&req);
+        match Page::from_request(&req) {
+            Page::Html => {}
+            Page::Text => {
+                let rendered = format::text_table(
+                    &resource,
+                    &Style {
+                        lang: Lang::En,
+                        ..style
+                    },
+                    &request_base(&req),
+                    &self.0.token_query(),
+                );
+                res.headers_mut().set(text_plain());
+                res.send(rendered.as_bytes()).unwrap();
+                return;
+            }
+            Page::Json => {
+                res.headers_mut().set(application_json());
+                res.send(json::encode(&resource).unwrap().as_bytes())
+                    .unwrap();
+                return;
+            }
+        }
+        let snippets = self.0.shared_snippets().map_or(Vec::new(), |s| s.list());
+        let rendered = format::html(
+            &self.0.theme.template("index.html"),
+            &resource,
+            &snippets,
+            format::Page {
+                delete: self.0.delete,
+                showdir: self.0.showdir,
+                upload: !self.0.no_upload,
+                dropbox: self.0.dropbox,
+                dir: self.0.path.clone(),
+                readme: self.0.readme(&resource),
+                trash: self.0.browsable_trash().is_some(),
+                versions: self.0.versioning().is_some(),
+            },
+            &style,
+        );
+        res.send(rendered.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for ListHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let resource = self
+            .0
+            .visible_resources(&self.0.sessions.from_request(&req));
+        let listing = request_path(&req)
+            .and_then(|p| Listing::from_path(&p))
+            .or_else(|| raw_header(&req, "Accept").and_then(|a| Listing::from_accept(&a)))
+            .unwrap_or(Listing::Text);
+
+        let base = request_base(&req);
+        let query = self.0.token_query();
+        let (rendered, file_name) = match listing {
+            Listing::Text => (format::file_list(&resource), "list.txt"),
+            Listing::Json => (json::encode(&resource).unwrap(), "list.json"),
+            Listing::Csv => (format::csv(&resource, &base, &query), "list.csv"),
+            Listing::Xml => (format::xml(&resource, &base, &query), "list.xml"),
+            Listing::Atom => {
+                let title = match self.0.directory.root.file_name() {
+                    Some(n) => format!("na: {}", n.to_string_lossy()),
+                    None => "na".to_string(),
+                };
+                (format::atom(&resource, &title, &base, &query), "feed.atom")
+            }
+            Listing::M3u => (format::m3u(&resource, &base, &query), "playlist.m3u8"),
+        };
+        if listing != Listing::Text {
+            res.headers_mut()
+                .set(ContentType(theme::content_type(file_name)));
+        }
+        res.send(rendered.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for HelpHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let resource = self
+            .0
+            .visible_resources(&self.0.sessions.from_request(&req));
+        let mut names: Vec<&str> = resource.iter().map(|f| f.name.as_str()).collect();
+        names.sort();
+        let rendered = format::help(
+            &request_base(&req),
+            self.0.token.as_ref().map(|t| t.as_str()),
+            names.first().cloned(),
+            !self.0.no_upload,
+            self.0.delete,
+        );
+        res.headers_mut().set(text_plain());
+        res.send(rendered.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for ApiListHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let mut resource = self
+            .0
+            .visible_resources(&self.0.sessions.from_request(&req));
+        resource.sort_by(|a, b| a.name.cmp(&b.name));
+        let files: Vec<api::File> = resource.iter().map(api::File::new).collect();
+        res.headers_mut().set(application_json());
+        res.send(json::encode(&files).unwrap().as_bytes()).unwrap();
+    }
+}
+
+impl Handler for ApiFileHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let session = self.0.sessions.from_request(&req);
+        let meta =
+            request_path(&req).and_then(|p| self.0.find_resource(&session, &file_target(&p)));
+        match meta {
+            Some(m) => {
+                res.headers_mut().set(application_json());
+                res.send(json::encode(&api::File::new(&m)).unwrap().as_bytes())
+                    .unwrap();
+            }
+            None => handler_404(req, res),
+        }
+    }
+}
+
+impl Handler for ApiInfoHandler {
+    fn handle(&self, _: Request, mut res: Response) {
+        let info = api::Info {
+            version: ::VERSION.to_string(),
+            api_version: 1,
+            upload: !self.0.no_upload,
+            delete: self.0.delete,
+            overwrite: self.0.replaces_files(),
+            dropbox: self.0.dropbox,
+            trash: self.0.browsable_trash().is_some(),
+            versions: self.0.versioning().is_some(),
+        };
+        res.headers_mut().set(application_json());
+        res.send(json::encode(&info).unwrap().as_bytes()).unwrap();
     }
 }
 
-impl Handler for ListHandler {
-    fn handle(&self, _: Request, res: Response) {
-        let resource = self.0.directory.list_available_resources();
-        let rendered = format::file_list(&resource);
-        res.send(rendered.as_bytes()).unwrap();
+impl Handler for ApiSpecHandler {
+    fn handle(&self, _: Request, mut res: Response) {
+        res.headers_mut().set(application_json());
+        res.send(api::openapi(::VERSION).as_bytes()).unwrap();
     }
 }
 
 impl Handler for JSONHandler {
-    fn handle(&self, _: Request, res: Response) {
-        let resource = self.0.directory.list_available_resources();
+    fn handle(&self, req: Request, res: Response) {
+        let resource = self
+            .0
+            .visible_resources(&self.0.sessions.from_request(&req));
         let rendered = json::encode(&resource).unwrap();
         res.send(rendered.as_bytes()).unwrap();
     }
@@ -159,15 +1191,11 @@ impl Handler for DeleteHandler {
             handler_405_delete(req, res);
             return;
         }
-        let resources = self.0.directory.list_available_resources();
+        let session = self.0.sessions.from_request(&req);
 
-        let uri: String = match req.uri {
-            RequestUri::AbsolutePath(ref path) => percent_decode((&path).as_bytes())
-                .decode_utf8()
-                .unwrap()
-                .deref()
-                .to_string(),
-            _ => {
+        let uri: String = match request_path(&req) {
+            Some(path) => path,
+            None => {
                 handler_404(req, res);
                 return;
             }
@@ -179,24 +1207,62 @@ impl Handler for DeleteHandler {
             uri
         );
 
-        let segments: Vec<&str> = uri.split("/").collect();
-        let str_name = segments.last().unwrap().to_string();
-        let mut name: Vec<u8> = Vec::new();
-        name.extend_from_slice(str_name.as_bytes());
-
-        let resource = match self.0.directory.get_resource(&str_name, &resources) {
+        let str_name = file_target(&uri);
+        let resource = match self.0.find_resource(&session, &str_name) {
             Some(r) => r.name.clone(),
             None => {
                 handler_404(req, res);
                 return;
             }
         };
-        let path = self.0.directory.full_path(&resource);
+        let path = match self.0.directory.resolve(&resource) {
+            Some(p) => p,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
 
-        match fs::remove_file(path.clone()) {
-            Ok(_) => {
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let deleted = match self.0.trash {
+            Some(ref trash) => trash.put(&path, &resource).map(Some),
+            None => fs::remove_file(path.clone()).map(|_| None),
+        };
+        match deleted {
+            Ok(trash_id) => {
+                self.0.directory.touch(&resource);
+                self.0.sessions.forget(&resource);
+                if let Some(ref versions) = self.0.versions {
+                    versions.forget(&resource);
+                }
                 let p = path.to_str().unwrap();
                 println_cond!(self.0.verbose, "Deleted file {}", p);
+                self.0.audit(Event::new(
+                    audit::DELETE,
+                    &resource,
+                    size,
+                    &req.remote_addr.to_string(),
+                    request_user(&req, &self.0.token),
+                ));
+                if let Some(ref hook) = self.0.hooks.delete {
+                    hook(&resource);
+                }
+                // Tells the page where to go to undo the delete.
+                if let (Some(id), Some(_)) = (trash_id, self.0.browsable_trash()) {
+                    res.headers_mut().set_raw(
+                        "Na-Restore",
+                        vec![format!("/trash/{}/restore", id).into_bytes()],
+                    );
+                }
+                if is_api(&req) {
+                    send_no_content(res);
+                    println_cond!(
+                        self.0.verbose,
+                        "Sending status code {}",
+                        StatusCode::NoContent.to_string()
+                    );
+                    return;
+                }
                 {
                     let stat: &mut StatusCode = res.status_mut();
                     *stat = StatusCode::Ok;
@@ -220,15 +1286,11 @@ impl Handler for DeleteHandler {
 
 impl Handler for FileDownloadHandler {
     fn handle(&self, req: Request, mut res: Response) {
-        let resources = self.0.directory.list_available_resources();
+        let session = self.0.sessions.from_request(&req);
 
-        let uri: String = match req.uri {
-            RequestUri::AbsolutePath(ref path) => percent_decode((&path).as_bytes())
-                .decode_utf8()
-                .unwrap()
-                .deref()
-                .to_string(),
-            _ => {
+        let uri: String = match request_path(&req) {
+            Some(path) => path,
+            None => {
                 handler_404(req, res);
                 return;
             }
@@ -240,23 +1302,172 @@ impl Handler for FileDownloadHandler {
             uri
         );
 
-        let segments: Vec<&str> = uri.split("/").collect();
-        let str_name = segments.last().unwrap().to_string();
+        let str_name = file_target(&uri);
         let mut name: Vec<u8> = Vec::new();
         name.extend_from_slice(str_name.as_bytes());
+        // HEAD asks about a file, such as for its digest, without
+        // downloading it.
+        let head = req.method == Method::Head;
 
-        let resource = match self.0.directory.get_resource(&str_name, &resources) {
+        let resource = match self.0.find_resource(&session, &str_name) {
             Some(r) => r.name.clone(),
             None => {
                 handler_404(req, res);
                 return;
             }
         };
-        let path = self.0.directory.full_path(&resource);
-        let meta = fs::metadata(&*path).unwrap();
-        let mut file: File = File::open(&*path).unwrap();
-        let len = meta.len() as usize;
+        if query_param(&req, "render").as_ref().map(|r| r.as_str()) == Some("1")
+            && markdown::is_markdown(&resource)
+        {
+            if let Some(content) = self.0.render_file(&resource) {
+                let page = format::document(
+                    &self.0.theme.template("markdown.html"),
+                    &resource,
+                    &format!("/files/{}", resource),
+                    &content,
+                );
+                res.headers_mut()
+                    .set(ContentType(theme::content_type(".html")));
+                res.send(page.as_bytes()).unwrap();
+                return;
+            }
+        }
+        let slot = if head {
+            None
+        } else {
+            match self.0.activity.reserve_download() {
+                Some(s) => Some(s),
+                None => {
+                    handler_503(res, is_api(&req), "Download limit reached.");
+                    return;
+                }
+            }
+        };
+        if let Some(ref stdin) = self.0.directory.stream {
+            if stdin.name == resource {
+                if head {
+                    send_head(res, stdin.is_taken());
+                    return;
+                }
+                if !stdin.take() {
+                    handler_404(req, res);
+                    return;
+                }
+                res.headers_mut().set(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(
+                        Charset::Ext("UTF-8".to_string()),
+                        None,
+                        name,
+                    )],
+                });
+                let mut stream = res.start().unwrap();
+                match stdin.copy_to(&mut stream) {
+                    Ok(sent) => {
+                        stream.end().unwrap();
+                        if let Some(slot) = slot {
+                            slot.complete(sent);
+                        }
+                        self.0.audit(Event::new(
+                            audit::DOWNLOAD,
+                            &resource,
+                            sent,
+                            &req.remote_addr.to_string(),
+                            request_user(&req, &self.0.token),
+                        ));
+                        println_cond!(
+                            self.0.verbose,
+                            "Streamed {} bytes from stdin to {}",
+                            sent,
+                            req.remote_addr.to_string()
+                        );
+                    }
+                    Err(e) => {
+                        printerr_cond!(
+                            self.0.verbose,
+                            "Error: Unexpected end of stream while sending stdin. [{}]",
+                            e
+                        );
+                    }
+                }
+                return;
+            }
+        }
+        let mut path = match self.0.directory.resolve(&resource) {
+            Some(p) => p,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        if let Some(v) = query_param(&req, "version") {
+            let version = match (self.0.versioning(), v.parse::<u64>()) {
+                (Some(versions), Ok(n)) => versions.resolve(&resource, n, &path),
+                _ => None,
+            };
+            path = match version {
+                Some(p) => p,
+                None => {
+                    handler_404(req, res);
+                    return;
+                }
+            };
+        }
+        // The file can go away or become unreadable after it was listed.
+        let opened = File::open(&*path).and_then(|f| f.metadata().map(|m| (f, m)));
+        let (mut file, meta) = match opened {
+            Ok(o) => o,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                handler_404(req, res);
+                return;
+            }
+            Err(e) => {
+                printerr_cond!(self.0.verbose, "Error: Couldn't open {}, {}", resource, e);
+                handler_500(req, res);
+                return;
+            }
+        };
+        let file_len = meta.len();
+        let mut len = file_len as usize;
+
+        match byte_range(&req, file_len) {
+            Ok(Some((start, end))) => {
+                {
+                    let stat: &mut StatusCode = res.status_mut();
+                    *stat = StatusCode::PartialContent;
+                }
+                res.headers_mut().set_raw(
+                    "Content-Range",
+                    vec![format!("bytes {}-{}/{}", start, end, file_len).into_bytes()],
+                );
+                file.seek(SeekFrom::Start(start)).unwrap();
+                len = (end - start + 1) as usize;
+            }
+            Ok(None) => {}
+            Err(_) => {
+                res.headers_mut().set_raw(
+                    "Content-Range",
+                    vec![format!("bytes */{}", file_len).into_bytes()],
+                );
+                let msg = "The range is outside of the file.";
+                let error = ApiError::new(StatusCode::RangeNotSatisfiable, "bad_range", msg);
+                send_error(res, is_api(&req), error);
+                return;
+            }
+        }
+        if wants_digest(&req) {
+            match checksum::sha256_file(&path) {
+                Ok(d) => res
+                    .headers_mut()
+                    .set_raw("Repr-Digest", vec![checksum::repr_digest(&d).into_bytes()]),
+                Err(e) => {
+                    printerr_cond!(self.0.verbose, "Error: Couldn't hash {}, {}", resource, e)
+                }
+            }
+        }
 
+        res.headers_mut()
+            .set_raw("Accept-Ranges", vec![b"bytes".to_vec()]);
         res.headers_mut().set(ContentLength(len as u64));
         res.headers_mut().set(ContentDisposition {
             disposition: DispositionType::Attachment,
@@ -266,6 +1477,10 @@ impl Handler for FileDownloadHandler {
                 name,
             )],
         });
+        if head {
+            let _ = res.start().and_then(|s| s.end());
+            return;
+        }
 
         let mut stream = res.start().unwrap();
         let mut buffer: [u8; 8192] = [0; 8192];
@@ -307,6 +1522,19 @@ impl Handler for FileDownloadHandler {
             sent_total = sent_total + sent;
         }
         stream.end().unwrap();
+        if let Some(slot) = slot {
+            slot.complete(sent_total as u64);
+        }
+        self.0.audit(Event::new(
+            audit::DOWNLOAD,
+            &resource,
+            sent_total as u64,
+            &req.remote_addr.to_string(),
+            request_user(&req, &self.0.token),
+        ));
+        if let Some(ref hook) = self.0.hooks.download {
+            hook(&resource);
+        }
 
         println_cond!(
             self.0.verbose,
@@ -321,8 +1549,23 @@ impl Handler for FileDownloadHandler {
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct SavedFile {
-    source_name: String,
-    saved_name: String,
+    pub source_name: String,
+    pub saved_name: String,
+    /// Hex encoded sha-256 digest of the saved file.
+    pub sha256: String,
+}
+
+fn upload_event(saved: &SavedFile, size: u64, remote: &str, user: &Option<String>) -> Event {
+    let mut event = Event::new(audit::UPLOAD, &saved.saved_name, size, remote, user.clone());
+    event.source_name = Some(saved.source_name.clone());
+    event.sha256 = Some(saved.sha256.clone());
+    event
+}
+
+pub enum UploadError {
+    /// The upload doesn't match the digest the client sent along.
+    Mismatch(String),
+    Io(io::Error),
 }
 
 impl Handler for FileUploadHandler {
@@ -332,12 +1575,26 @@ impl Handler for FileUploadHandler {
             return;
         }
         let remote_address = req.remote_addr.to_string();
+        let user = request_user(&req, &self.0.token);
         println_cond!(
             self.0.verbose,
             "Receiving a POST request from {}",
             remote_address
         );
 
+        let session = match self.0.sessions.from_request(&req) {
+            Some(id) => id,
+            None => {
+                let id = self.0.sessions.create();
+                if self.0.dropbox {
+                    res.headers_mut()
+                        .set_raw("Set-Cookie", vec![Sessions::cookie(&id)]);
+                }
+                id
+            }
+        };
+
+        let api = is_api(&req);
         let multipart = Multipart::from_request(req).ok();
         if multipart.is_none() {
             printerr_cond!(
@@ -346,16 +1603,23 @@ impl Handler for FileUploadHandler {
                                      Multipart missing!",
                 remote_address
             );
-            handler_400(res, "400 Bad Request. Multipart missing!\n");
+            handler_400(res, api, "Multipart missing!");
             return;
         }
 
         let mut mpu = multipart.unwrap();
         let mut saved_files: Vec<SavedFile> = Vec::new();
+        // Files that couldn't be saved don't stop the ones after them.
+        let mut failed: Vec<ApiError> = Vec::new();
+        let mut snippet_text: Option<String> = None;
+        let mut snippet_ttl = None;
+        let mut expected: Option<Result<Vec<Expected>, String>> = None;
 
         while let Ok(Some(field)) = mpu.read_entry() {
             match field.data {
                 MultipartData::File(mut file) => {
+                    // A digest field applies to the file that follows it.
+                    let expected = expected.take().unwrap_or(Ok(Vec::new()));
                     let src_name = match file.filename() {
                         Some(n) => n.to_string(),
                         None => {
@@ -365,74 +1629,422 @@ impl Handler for FileUploadHandler {
                                            File name missing!\n",
                                 remote_address
                             );
-                            handler_400(res, "Bad request (400). File name missing!\n");
-                            return;
+                            let msg = "File name missing!";
+                            failed.push(ApiError::new(StatusCode::BadRequest, "bad_request", msg));
+                            continue;
                         }
                     };
-                    let available_name = if self.0.overwrite {
-                        src_name.clone()
-                    } else {
-                        self.0.directory.get_available_name(&src_name)
+                    let expected = match expected {
+                        Ok(e) => e,
+                        Err(e) => {
+                            printerr_cond!(
+                                self.0.verbose,
+                                "Error: Skipped {} from {}, {}",
+                                src_name,
+                                remote_address,
+                                e
+                            );
+                            let msg = format!("{}: {}", src_name, e);
+                            let mut error =
+                                ApiError::new(StatusCode::BadRequest, "bad_request", &msg);
+                            error.file = Some(src_name);
+                            failed.push(error);
+                            continue;
+                        }
                     };
-                    let path = self.0.directory.full_path(&available_name);
-                    match file.save_as(path) {
-                        Ok(f) => {
-                            let p = f.path.to_str().unwrap();
-                            println_cond!(self.0.verbose, "Written {} bytes to {}", f.size, p);
-                            saved_files.push(SavedFile {
+                    match self.0.save_upload(&mut file, &src_name, &expected, false) {
+                        Ok((available_name, size, sha256)) => {
+                            println_cond!(
+                                self.0.verbose,
+                                "Written {} bytes to {}",
+                                size,
+                                available_name
+                            );
+                            self.0.activity.record_upload(size);
+                            if self.0.dropbox {
+                                self.0.claim(&session, &available_name);
+                            }
+                            let saved = SavedFile {
                                 source_name: src_name.clone(),
-                                saved_name: available_name.clone(),
-                            });
+                                saved_name: available_name,
+                                sha256: sha256.to_hex(),
+                            };
+                            self.0
+                                .audit(upload_event(&saved, size, &remote_address, &user));
+                            if let Some(ref hook) = self.0.hooks.upload {
+                                hook(&saved);
+                            }
+                            saved_files.push(saved);
                         }
-                        Err(e) => {
+                        Err(UploadError::Mismatch(e)) => {
+                            printerr_cond!(
+                                self.0.verbose,
+                                "Error: Discarded {} from {}, {}",
+                                src_name,
+                                remote_address,
+                                e
+                            );
+                            let msg = format!("{}: {}", src_name, e);
+                            let mut error =
+                                ApiError::new(StatusCode::BadRequest, "digest_mismatch", &msg);
+                            error.file = Some(src_name);
+                            failed.push(error);
+                        }
+                        Err(UploadError::Io(e)) => {
                             printerr_cond!(
                                 self.0.verbose,
                                 "Error: Couldn't save {} to disk! \
                                                             {}",
-                                available_name,
+                                src_name,
                                 e
                             );
+                            let msg = format!("Couldn't save {}", src_name);
+                            let mut error =
+                                ApiError::new(StatusCode::InternalServerError, "io_error", &msg);
+                            error.file = Some(src_name);
+                            failed.push(error);
                         }
                     }
                 }
-                MultipartData::Text(_) => {}
+                MultipartData::Text(text) => match field.name.as_str() {
+                    "digest" => expected = Some(checksum::parse_expected(text)),
+                    "snippet" if text.len() as u64 > MAX_SNIPPET_SIZE => {
+                        let msg = "Snippet is too large!";
+                        failed.push(ApiError::new(StatusCode::PayloadTooLarge, "too_large", msg));
+                    }
+                    "snippet" => snippet_text = Some(text.to_string()),
+                    "expires" => snippet_ttl = shutdown::parse_duration(text).ok(),
+                    _ => {}
+                },
+            }
+        }
+        if let (Some(text), Some(snippets)) = (snippet_text, self.0.shared_snippets()) {
+            if !text.trim().is_empty() {
+                let snippet = snippets.add(text, snippet_ttl);
+                println_cond!(
+                    self.0.verbose,
+                    "Added snippet {} from {}",
+                    snippet.id,
+                    remote_address
+                );
+            }
+        }
+
+        if !failed.is_empty() {
+            if !api || saved_files.is_empty() {
+                send_error(res, api, failed.remove(0));
+                return;
             }
+            *res.status_mut() = StatusCode::MultiStatus;
+            res.headers_mut().set(application_json());
+            let report = UploadReport {
+                saved: saved_files,
+                failed: failed,
+            };
+            res.send(format!("{}\n", json::encode(&report).unwrap()).as_bytes())
+                .unwrap();
+            return;
         }
+
+        // Browsers are sent back to the page, programs are told what
+        // was created.
+        let status = if api {
+            StatusCode::Created
+        } else {
+            StatusCode::Found
+        };
         {
             let stat = res.status_mut();
-            *stat = StatusCode::Found;
+            *stat = status;
+        }
+        if api {
+            if saved_files.len() == 1 {
+                res.headers_mut().set(Location(format!(
+                    "/files/{}",
+                    utf8_percent_encode(&saved_files[0].saved_name, format::PATH_SEGMENT)
+                )));
+            }
+            res.headers_mut().set(application_json());
+        } else {
+            res.headers_mut().set(Location("/".to_string()));
         }
-
-        res.headers_mut().set(Location("/".to_string()));
 
         let saved_files_json = json::encode(&saved_files).unwrap();
 
         res.send(format!("{}\n", saved_files_json).as_bytes())
             .unwrap();
 
+        println_cond!(self.0.verbose, "Sending status code {}", status.to_string());
+    }
+}
+
+impl Handler for FilePutHandler {
+    fn handle(&self, mut req: Request, mut res: Response) {
+        if self.0.no_upload {
+            handler_405_put(req, res);
+            return;
+        }
+        let api = is_api(&req);
+        let remote_address = req.remote_addr.to_string();
+        let user = request_user(&req, &self.0.token);
+        let uri: String = match request_path(&req) {
+            Some(path) => path,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        println_cond!(
+            self.0.verbose,
+            "Receiving a PUT request from {} for {}",
+            remote_address,
+            uri
+        );
+
+        let src_name = uri.split("/").last().unwrap().to_string();
+
+        let session = match self.0.sessions.from_request(&req) {
+            Some(id) => id,
+            None => {
+                let id = self.0.sessions.create();
+                if self.0.dropbox {
+                    res.headers_mut()
+                        .set_raw("Set-Cookie", vec![Sessions::cookie(&id)]);
+                }
+                id
+            }
+        };
+
+        // "If-None-Match: *" asks for the upload to fail rather than
+        // replace or sit next to an existing file. It's checked once more
+        // when the upload is saved, in case the file was created since.
+        let if_none_match = raw_header(&req, "If-None-Match").map_or(false, |v| v.trim() == "*");
+        let exists = self
+            .0
+            .find_resource(&Some(session.clone()), &directory::sanitize_name(&src_name))
+            .is_some();
+        if exists && if_none_match {
+            handler_412(res, api, "The file already exists.");
+            return;
+        }
+        // A drop box saves next to the files of others, which must not
+        // give away that they exist.
+        let exclusive = if_none_match && !self.0.dropbox;
+        let mut expected = Vec::new();
+        let headers = ["Content-Digest", "Repr-Digest"];
+        for value in headers.iter().filter_map(|h| raw_header(&req, h)) {
+            match checksum::parse_expected(&value) {
+                Ok(e) => expected.extend(e),
+                Err(e) => {
+                    handler_400(res, api, &e);
+                    return;
+                }
+            }
+        }
+        if let Some(value) = raw_header(&req, "Content-MD5") {
+            match checksum::parse_content_md5(&value) {
+                Ok(e) => expected.push(e),
+                Err(e) => {
+                    handler_400(res, api, &e);
+                    return;
+                }
+            }
+        }
+
+        // The request reader takes care of both Content-Length and
+        // chunked bodies.
+        let (available_name, size, sha256) =
+            match self.0.save_upload(&mut req, &src_name, &expected, exclusive) {
+                Ok(s) => s,
+                Err(UploadError::Mismatch(e)) => {
+                    printerr_cond!(
+                        self.0.verbose,
+                        "Error: Discarded {} from {}, {}",
+                        src_name,
+                        remote_address,
+                        e
+                    );
+                    handler_400(res, api, &format!("{}: {}", src_name, e));
+                    return;
+                }
+                Err(UploadError::Io(ref e)) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    handler_412(res, api, "The file already exists.");
+                    return;
+                }
+                Err(UploadError::Io(e)) => {
+                    printerr_cond!(
+                        self.0.verbose,
+                        "Error: Couldn't save {} to disk! {}",
+                        src_name,
+                        e
+                    );
+                    handler_500(req, res);
+                    return;
+                }
+            };
         println_cond!(
             self.0.verbose,
-            "Sending status code {}",
-            StatusCode::Found.to_string()
+            "Written {} bytes to {}",
+            size,
+            available_name
         );
+        self.0.activity.record_upload(size);
+        if self.0.dropbox {
+            self.0.claim(&session, &available_name);
+        }
+        let saved = SavedFile {
+            source_name: src_name,
+            saved_name: available_name,
+            sha256: sha256.to_hex(),
+        };
+        self.0
+            .audit(upload_event(&saved, size, &remote_address, &user));
+        if let Some(ref hook) = self.0.hooks.upload {
+            hook(&saved);
+        }
+
+        {
+            let stat = res.status_mut();
+            *stat = StatusCode::Created;
+        }
+        res.headers_mut().set(Location(format!(
+            "/files/{}",
+            utf8_percent_encode(&saved.saved_name, format::PATH_SEGMENT)
+        )));
+        res.headers_mut().set(application_json());
+        res.send(format!("{}\n", json::encode(&saved).unwrap()).as_bytes())
+            .unwrap();
     }
 }
 
 impl Handler for StaticResourceHandler {
-    fn handle(&self, req: Request, res: Response) {
-        let uri: String = match req.uri {
-            RequestUri::AbsolutePath(path) => percent_decode((&path).as_bytes())
-                .decode_utf8()
-                .unwrap()
-                .deref()
-                .to_string(),
-            RequestUri::AbsoluteUri(uri) => uri.to_string(),
-            _ => "fixme".to_string(),
-        };
-        if self.0.resource.r.contains_key(uri.as_str()) {
-            res.send(self.0.resource.r.get(uri.as_str()).unwrap().as_bytes())
-                .unwrap();
+    fn handle(&self, req: Request, mut res: Response) {
+        let uri: String = match request_path(&req) {
+            Some(path) => path,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        let name = uri.rsplit('/').next().unwrap();
+        match self.0.theme.get(name) {
+            Some(contents) => {
+                res.headers_mut()
+                    .set(ContentType(theme::content_type(name)));
+                res.send(&contents).unwrap();
+            }
+            None => handler_404(req, res),
+        }
+    }
+}
+
+impl Handler for SnippetListHandler {
+    fn handle(&self, _: Request, mut res: Response) {
+        let snippets = self.0.shared_snippets().map_or(Vec::new(), |s| s.list());
+        res.headers_mut().set(application_json());
+        res.send(json::encode(&snippets).unwrap().as_bytes())
+            .unwrap();
+    }
+}
+
+impl Handler for SnippetHandler {
+    fn handle(&self, req: Request, mut res: Response) {
+        let snippets = self.0.shared_snippets();
+        let snippet = match snippet_id(&req).and_then(|id| snippets.and_then(|s| s.get(id))) {
+            Some(s) => s,
+            None => {
+                handler_404(req, res);
+                return;
+            }
+        };
+        res.headers_mut().set(text_plain());
+        res.send(snippet.text.as_bytes()).unwrap();
+    }
+}
+
+impl Handler for SnippetUploadHandler {
+    fn handle(&self, mut req: Request, mut res: Response) {
+        let snippets = match self.0.shared_snippets() {
+            Some(s) if !self.0.no_upload => s,
+            _ => {
+                handler_405_post(req, res);
+                return;
+            }
+        };
+        let api = is_api(&req);
+        let ttl = match query_param(&req, "expires") {
+            Some(e) => match shutdown::parse_duration(&e) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    handler_400(res, api, &e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        // One byte more than allowed tells a snippet that's too large from
+        // one that just fits.
+        let mut body = Vec::new();
+        if let Err(e) = req
+            .by_ref()
+            .take(MAX_SNIPPET_SIZE + 1)
+            .read_to_end(&mut body)
+        {
+            handler_400(res, api, &e.to_string());
+            return;
+        }
+        if body.len() as u64 > MAX_SNIPPET_SIZE {
+            handler_413(res, api, "Snippet is too large!");
+            return;
+        }
+        let text = match String::from_utf8(body) {
+            Ok(t) => t,
+            Err(_) => {
+                handler_400(res, api, "Snippet isn't UTF-8 text!");
+                return;
+            }
+        };
+        if text.trim().is_empty() {
+            handler_400(res, api, "Snippet is empty!");
             return;
         }
+        let snippet = snippets.add(text, ttl);
+        println_cond!(
+            self.0.verbose,
+            "Added snippet {} from {}",
+            snippet.id,
+            req.remote_addr.to_string()
+        );
+        {
+            let stat = res.status_mut();
+            *stat = StatusCode::Created;
+        }
+        res.headers_mut().set(Location(snippet.url.clone()));
+        res.headers_mut().set(application_json());
+        res.send(format!("{}\n", json::encode(&snippet).unwrap()).as_bytes())
+            .unwrap();
+    }
+}
+
+impl Handler for SnippetDeleteHandler {
+    fn handle(&self, req: Request, res: Response) {
+        let snippets = match self.0.shared_snippets() {
+            Some(s) if self.0.delete => s,
+            _ => {
+                handler_405_delete(req, res);
+                return;
+            }
+        };
+        match snippet_id(&req) {
+            Some(id) if snippets.remove(id) => {
+                if is_api(&req) {
+                    send_no_content(res);
+                    return;
+                }
+                res.send(format!("Successfully deleted snippet {}\n", id).as_bytes())
+                    .unwrap();
+            }
+            _ => handler_404(req, res),
+        }
     }
 }
@@ -0,0 +1,561 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use hyper::server::Handler;
+use hyper_router::{Route, RouterBuilder};
+use listener::{Listener, Serving};
+use rustc_serialize::json;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use audit::AuditLog;
+use directory::Directory;
+use locale::Units;
+use routes::{
+    self, ApiFileHandler, ApiInfoHandler, ApiListHandler, ApiSpecHandler, DeleteHandler,
+    FileDownloadHandler, FilePutHandler, FileUploadHandler, HandlerState, HelpHandler, Hooks,
+    IndexHandler, JSONHandler, ListHandler, SavedFile, SnippetDeleteHandler, SnippetHandler,
+    SnippetListHandler, SnippetUploadHandler, StaticResourceHandler, TrackingHandler, TrashHandler,
+    TrashPurgeHandler, TrashRestoreHandler, VersionRestoreHandler, VersionsHandler,
+};
+use session::Sessions;
+use shutdown::{Activity, Reason, ShutdownPolicy};
+use snippet::Snippets;
+use theme::Theme;
+use trash::{self, Trash};
+use versions::{VersionPolicy, Versions};
+
+/// Configures and starts an na server.
+///
+/// ```no_run
+/// use na::{Directory, ServerBuilder};
+/// use std::path::PathBuf;
+///
+/// let handle = ServerBuilder::new(Directory::new(PathBuf::from("/tmp")))
+///     .bind("127.0.0.1:0")
+///     .enable_delete(true)
+///     .start()
+///     .unwrap();
+/// println!("listening on {}", handle.addr());
+/// handle.shutdown();
+/// ```
+pub struct ServerBuilder {
+    directory: Directory,
+    addrs: Vec<String>,
+    fallback_ports: Vec<u16>,
+    verbose: bool,
+    delete: bool,
+    upload: bool,
+    showdir: bool,
+    overwrite: bool,
+    dropbox: bool,
+    token: Option<String>,
+    max_downloads: Option<usize>,
+    theme_dir: Option<PathBuf>,
+    hard_delete: bool,
+    trash_retention: Duration,
+    versions: Option<VersionPolicy>,
+    audit_log: Option<PathBuf>,
+    index_rescan: Option<Duration>,
+    units: Units,
+    hooks: Hooks,
+    routes: Vec<Route>,
+}
+
+/// A line of JSON describing a running server, so that scripts can find
+/// one that picked its own port.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Startup {
+    pub version: String,
+    pub pid: u32,
+    pub port: u16,
+    pub addrs: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+/// A running server.
+pub struct ServerHandle {
+    listening: Vec<Serving>,
+    state: Arc<HandlerState>,
+}
+
+impl ServerBuilder {
+    pub fn new(directory: Directory) -> ServerBuilder {
+        ServerBuilder {
+            directory,
+            addrs: vec!["127.0.0.1:8888".to_string()],
+            fallback_ports: Vec::new(),
+            verbose: false,
+            delete: false,
+            upload: true,
+            showdir: false,
+            overwrite: false,
+            dropbox: false,
+            token: None,
+            max_downloads: None,
+            theme_dir: None,
+            hard_delete: false,
+            trash_retention: Duration::from_secs(trash::DEFAULT_RETENTION),
+            versions: None,
+            audit_log: None,
+            index_rescan: None,
+            units: Units::Si,
+            hooks: Hooks::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Address to listen on. Use port 0 to let the OS pick a free port.
+    pub fn bind(mut self, addr: &str) -> ServerBuilder {
+        self.addrs = vec![addr.to_string()];
+        self
+    }
+
+    /// Another address to listen on, next to the ones already given.
+    /// All of them serve the same files.
+    pub fn also_bind(mut self, addr: &str) -> ServerBuilder {
+        self.addrs.push(addr.to_string());
+        self
+    }
+
+    /// Ports to try in turn when the port of the listen addresses is
+    /// taken. Every address moves to the same port, where 0 lets the OS
+    /// pick a free one.
+    pub fn fallback_ports(mut self, ports: Vec<u16>) -> ServerBuilder {
+        self.fallback_ports = ports;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> ServerBuilder {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Lets clients delete files. Files shared one by one are left alone,
+    /// they're the originals of whoever started the server.
+    pub fn enable_delete(mut self, delete: bool) -> ServerBuilder {
+        self.delete = delete;
+        self
+    }
+
+    pub fn enable_upload(mut self, upload: bool) -> ServerBuilder {
+        self.upload = upload;
+        self
+    }
+
+    pub fn show_directory(mut self, show: bool) -> ServerBuilder {
+        self.showdir = show;
+        self
+    }
+
+    pub fn overwrite(mut self, overwrite: bool) -> ServerBuilder {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn dropbox(mut self, dropbox: bool) -> ServerBuilder {
+        self.dropbox = dropbox;
+        self
+    }
+
+    pub fn token(mut self, token: Option<String>) -> ServerBuilder {
+        self.token = token;
+        self
+    }
+
+    pub fn max_downloads(mut self, max: Option<usize>) -> ServerBuilder {
+        self.max_downloads = max;
+        self
+    }
+
+    /// Directory with an `index.html`, stylesheets, scripts or other
+    /// assets that replace the built-in ones.
+    pub fn theme_dir(mut self, dir: Option<PathBuf>) -> ServerBuilder {
+        self.theme_dir = dir;
+        self
+    }
+
+    /// Removes deleted files right away instead of moving them to the
+    /// trash.
+    pub fn hard_delete(mut self, hard: bool) -> ServerBuilder {
+        self.hard_delete = hard;
+        self
+    }
+
+    /// How long deleted files are kept in the trash.
+    pub fn trash_retention(mut self, retention: Duration) -> ServerBuilder {
+        self.trash_retention = retention;
+        self
+    }
+
+    /// Keeps older revisions when an upload replaces a file, instead of
+    /// saving it under a new name.
+    pub fn versions(mut self, policy: Option<VersionPolicy>) -> ServerBuilder {
+        self.versions = policy;
+        self
+    }
+
+    /// File to append a JSON line to for every upload, download,
+    /// deletion, restore and purge.
+    pub fn audit_log(mut self, path: Option<PathBuf>) -> ServerBuilder {
+        self.audit_log = path;
+        self
+    }
+
+    /// Keeps the listing of the served directory in memory instead of
+    /// reading it for every request. If changes to the directory can't
+    /// be watched, it's read again every `rescan`.
+    pub fn index(mut self, rescan: Option<Duration>) -> ServerBuilder {
+        self.index_rescan = rescan;
+        self
+    }
+
+    /// Units file sizes are shown in on the page.
+    pub fn units(mut self, units: Units) -> ServerBuilder {
+        self.units = units;
+        self
+    }
+
+    /// Shows times in UTC rather than the local timezone.
+    pub fn utc(mut self, utc: bool) -> ServerBuilder {
+        self.directory.utc = utc;
+        self
+    }
+
+    pub fn on_upload<F>(mut self, hook: F) -> ServerBuilder
+    where
+        F: Fn(&SavedFile) + Send + Sync + 'static,
+    {
+        self.hooks.upload = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_download<F>(mut self, hook: F) -> ServerBuilder
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.hooks.download = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_delete<F>(mut self, hook: F) -> ServerBuilder
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.hooks.delete = Some(Box::new(hook));
+        self
+    }
+
+    /// Adds a route of the embedder's own. These are matched before the
+    /// built-in ones.
+    pub fn route(mut self, route: Route) -> ServerBuilder {
+        self.routes.push(route);
+        self
+    }
+
+    pub fn start(mut self) -> Result<ServerHandle, String> {
+        let audit = match self.audit_log {
+            // Anyone could download it, or upload over it.
+            Some(ref p) if is_inside(&self.directory.root, p) => {
+                return Err(format!(
+                    "The audit log {} can't be kept in the served directory",
+                    p.to_string_lossy()
+                ))
+            }
+            Some(ref p) => match AuditLog::open(p) {
+                Ok(log) => Some(log),
+                Err(e) => {
+                    return Err(format!(
+                        "Unable to open audit log {}, {}",
+                        p.to_string_lossy(),
+                        e
+                    ))
+                }
+            },
+            None => None,
+        };
+        let path = self.directory.root.to_str().unwrap().to_string();
+        let stale = self.directory.remove_stale_partials();
+        println_cond!(
+            self.verbose && stale > 0,
+            "Removed {} unfinished uploads left over from an earlier run",
+            stale
+        );
+
+        if let Some(rescan) = self.index_rescan {
+            if let Err(e) = self.directory.cache_index(rescan) {
+                println_cond!(
+                    self.verbose,
+                    "Unable to watch {} for changes, {}. Reading it again every {} seconds.",
+                    path,
+                    e,
+                    rescan.as_secs()
+                );
+            }
+        }
+
+        let trash = if self.hard_delete {
+            None
+        } else {
+            let trash = Trash::new(&self.directory.root, self.trash_retention);
+            trash.purge_expired();
+            Some(trash)
+        };
+
+        let versions = self
+            .versions
+            .map(|policy| Versions::new(&self.directory.root, policy));
+        let delete = self.delete && self.directory.files.is_none();
+
+        let hs = Arc::new(HandlerState {
+            directory: self.directory,
+            theme: Theme::new(self.theme_dir),
+            verbose: self.verbose,
+            delete: delete,
+            no_upload: !self.upload,
+            showdir: self.showdir,
+            overwrite: self.overwrite,
+            path: path,
+            activity: Activity::new(self.max_downloads),
+            dropbox: self.dropbox,
+            sessions: Sessions::new(),
+            snippets: Snippets::new(),
+            token: self.token,
+            hooks: self.hooks,
+            trash: trash,
+            versions: versions,
+            audit: audit,
+            units: self.units,
+        });
+
+        let mut router = RouterBuilder::new();
+        for r in self.routes {
+            router = router.add(r);
+        }
+        let router = router
+            .add(Route::get(r"(/|/index\.html)(\?.*)?$").using(IndexHandler(hs.clone())))
+            .add(Route::post(r"(/|/index\.html)(\?.*)?$").using(FileUploadHandler(hs.clone())))
+            .add(Route::delete(r"/files/[^/]+$").using(DeleteHandler(hs.clone())))
+            .add(Route::get(r"/files/[^/]+$").using(FileDownloadHandler(hs.clone())))
+            .add(Route::head(r"/files/[^/]+$").using(FileDownloadHandler(hs.clone())))
+            .add(Route::put(r"/files/[^/]+$").using(FilePutHandler(hs.clone())))
+            .add(Route::get(r"/resource/[^/]+$").using(StaticResourceHandler(hs.clone())))
+            .add(Route::get(r"/favicon.ico$").using(StaticResourceHandler(hs.clone())))
+            .add(Route::get(r"/json").using(JSONHandler(hs.clone())))
+            .add(Route::get(r"/help$").using(HelpHandler(hs.clone())))
+            .add(Route::get(r"/api/v1/info$").using(ApiInfoHandler(hs.clone())))
+            .add(Route::get(r"/api/v1/openapi.json$").using(ApiSpecHandler(hs.clone())))
+            .add(Route::get(r"/api/v1/files$").using(ApiListHandler(hs.clone())))
+            .add(Route::post(r"/api/v1/files$").using(FileUploadHandler(hs.clone())))
+            .add(Route::get(r"/api/v1/files/[^/]+$").using(ApiFileHandler(hs.clone())))
+            .add(Route::put(r"/api/v1/files/[^/]+$").using(FilePutHandler(hs.clone())))
+            .add(Route::delete(r"/api/v1/files/[^/]+$").using(DeleteHandler(hs.clone())))
+            .add(Route::get(r"/api/v1/files/[^/]+/content$").using(FileDownloadHandler(hs.clone())))
+            .add(Route::head(r"/api/v1/files/[^/]+/content$").using(FileDownloadHandler(hs.clone())))
+            // Feed readers and players pass the token in the query.
+            .add(Route::get(r"/list(\?.*)?$").using(ListHandler(hs.clone())))
+            .add(Route::get(r"/list\.csv(\?.*)?$").using(ListHandler(hs.clone())))
+            .add(Route::get(r"/list\.xml(\?.*)?$").using(ListHandler(hs.clone())))
+            .add(Route::get(r"/feed\.atom(\?.*)?$").using(ListHandler(hs.clone())))
+            .add(Route::get(r"/playlist\.m3u8(\?.*)?$").using(ListHandler(hs.clone())))
+            .add(Route::get(r"/trash$").using(TrashHandler(hs.clone())))
+            .add(Route::delete(r"/trash$").using(TrashPurgeHandler(hs.clone())))
+            .add(Route::delete(r"/trash/[0-9a-f]+$").using(TrashPurgeHandler(hs.clone())))
+            .add(Route::post(r"/trash/[0-9a-f]+/restore$").using(TrashRestoreHandler(hs.clone())))
+            .add(Route::get(r"/versions/[^/]+$").using(VersionsHandler(hs.clone())))
+            .add(Route::post(r"/versions/[^/]+/[0-9]+$").using(VersionRestoreHandler(hs.clone())))
+            .add(Route::get(r"/snippets/[0-9]+$").using(SnippetHandler(hs.clone())))
+            .add(Route::delete(r"/snippets/[0-9]+$").using(SnippetDeleteHandler(hs.clone())))
+            .add(Route::get(r"/snippets(\?.*)?$").using(SnippetListHandler(hs.clone())))
+            .add(Route::post(r"/snippets(\?.*)?$").using(SnippetUploadHandler(hs.clone())))
+            .set_handler_404(routes::handler_404)
+            .set_handler_405(routes::handler_405)
+            .set_handler_500(routes::handler_500)
+            .build();
+        let router = Arc::new(router);
+
+        // A dual-stack "::" has to be bound before "0.0.0.0", see
+        // covered_by_dual_stack.
+        let mut addrs = self.addrs;
+        addrs.sort_by_key(|a| match a.parse::<SocketAddr>() {
+            Ok(SocketAddr::V6(ref v6)) if v6.ip().is_unspecified() => 0,
+            _ => 1,
+        });
+        let mut started = listen_all(&addrs, &hs, &router, self.verbose);
+        for &port in &self.fallback_ports {
+            match started {
+                Err(ref e) if e.in_use => {
+                    println_cond!(self.verbose, "{}, trying port {}", e.message, port)
+                }
+                _ => break,
+            }
+            let moved: Vec<String> = addrs.iter().map(|a| with_port(a, port)).collect();
+            started = listen_all(&moved, &hs, &router, self.verbose);
+        }
+        match started {
+            Ok(listening) => Ok(ServerHandle {
+                listening,
+                state: hs,
+            }),
+            Err(e) => Err(e.message),
+        }
+    }
+}
+
+struct BindError {
+    message: String,
+    /// Whether the port was taken, in which case another one can be tried.
+    in_use: bool,
+}
+
+/// Starts a server for each of the addresses, or none at all.
+fn listen_all<H: Handler + 'static>(
+    addrs: &[String],
+    hs: &Arc<HandlerState>,
+    router: &Arc<H>,
+    verbose: bool,
+) -> Result<Vec<Serving>, BindError> {
+    let mut listening: Vec<Serving> = Vec::new();
+    for addr in addrs {
+        match Listener::bind(addr) {
+            Ok(l) => listening.push(Serving::start(
+                l,
+                TrackingHandler(hs.clone(), router.clone()),
+            )),
+            Err(_) if covered_by_dual_stack(addr, &listening) => {
+                println_cond!(
+                    verbose,
+                    "Not binding {} separately, the IPv6 socket accepts IPv4 connections too",
+                    addr
+                );
+            }
+            Err(e) => {
+                for s in listening {
+                    s.close();
+                }
+                return Err(BindError {
+                    message: format!("Unable to start na at ({}), {}", addr, e),
+                    in_use: e.kind() == io::ErrorKind::AddrInUse,
+                });
+            }
+        }
+    }
+    if listening.is_empty() {
+        return Err(BindError {
+            message: "No address to listen on".to_string(),
+            in_use: false,
+        });
+    }
+    Ok(listening)
+}
+
+/// Whether `path`, which doesn't have to exist yet, is somewhere inside
+/// `dir`.
+fn is_inside(dir: &Path, path: &Path) -> bool {
+    let dir = match dir.canonicalize() {
+        Ok(d) => d,
+        Err(_) => return false,
+    };
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    parent.canonicalize().map_or(false, |p| p.starts_with(&dir))
+}
+
+/// Replaces the port of a listen address.
+fn with_port(addr: &str, port: u16) -> String {
+    if let Ok(mut a) = addr.parse::<SocketAddr>() {
+        a.set_port(port);
+        return a.to_string();
+    }
+    match addr.rfind(':') {
+        Some(i) => format!("{}:{}", &addr[..i], port),
+        None => format!("{}:{}", addr, port),
+    }
+}
+
+/// On most systems a socket bound to "::" also accepts IPv4 connections,
+/// which keeps "0.0.0.0" on the same port from being bound.
+fn covered_by_dual_stack(addr: &str, listening: &[Serving]) -> bool {
+    match addr.parse::<SocketAddr>() {
+        Ok(SocketAddr::V4(ref v4)) if v4.ip().is_unspecified() => listening.iter().any(|s| {
+            let socket = s.addr();
+            socket.is_ipv6() && socket.ip().is_unspecified() && socket.port() == v4.port()
+        }),
+        _ => false,
+    }
+}
+
+impl ServerHandle {
+    /// The address the server is actually bound to. With more than one,
+    /// that's the first of them.
+    pub fn addr(&self) -> SocketAddr {
+        self.listening[0].addr()
+    }
+
+    /// Every address the server is bound to.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.listening.iter().map(|s| s.addr()).collect()
+    }
+
+    /// Describes the server as JSON, with the URLs it can be reached at.
+    pub fn startup_json(&self, urls: &[String]) -> String {
+        let startup = Startup {
+            version: ::VERSION.to_string(),
+            pid: process::id(),
+            port: self.addr().port(),
+            addrs: self.addrs().iter().map(|a| a.to_string()).collect(),
+            urls: urls.to_vec(),
+        };
+        json::encode(&startup).unwrap()
+    }
+
+    pub fn state(&self) -> &Arc<HandlerState> {
+        &self.state
+    }
+
+    /// Blocks until the policy says it's time to stop, then shuts down.
+    pub fn run_until(self, policy: &ShutdownPolicy) -> Reason {
+        let reason = self.state.activity.wait(policy);
+        self.shutdown();
+        reason
+    }
+
+    /// Blocks for as long as the server is running.
+    pub fn join(self) {
+        for s in self.listening {
+            s.join();
+        }
+    }
+
+    /// Refuses new requests, waits for the ones in flight and stops.
+    /// The addresses can be bound again afterwards. Called from a hook
+    /// or a handler, the request it's called from finishes afterwards.
+    pub fn shutdown(self) {
+        self.state.activity.close();
+        for s in &self.listening {
+            s.close();
+        }
+    }
+}
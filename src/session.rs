@@ -0,0 +1,120 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use directory::FileId;
+use hyper::server::Request;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const COOKIE_NAME: &'static str = "na_session";
+
+/// Returns the value of the cookie called `name`, if the request has one.
+pub fn cookie_value(req: &Request, name: &str) -> Option<String> {
+    let raw = match req.headers.get_raw("Cookie") {
+        Some(r) => r,
+        None => return None,
+    };
+    for line in raw {
+        let line = match str::from_utf8(line) {
+            Ok(l) => l,
+            Err(_) => continue,
+        };
+        for pair in line.split(';') {
+            let mut kv = pair.trim().splitn(2, '=');
+            if kv.next() == Some(name) {
+                if let Some(v) = kv.next() {
+                    return Some(v.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Keeps track of which files were uploaded by which browser session.
+/// Files are remembered by name and id, so that a session doesn't get to
+/// see another file that was later saved under the same name.
+pub struct Sessions {
+    uploads: Mutex<HashMap<String, Vec<(String, FileId)>>>,
+    counter: AtomicUsize,
+    seed: RandomState,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions {
+            uploads: Mutex::new(HashMap::new()),
+            counter: AtomicUsize::new(0),
+            seed: RandomState::new(),
+        }
+    }
+
+    /// Returns the session id sent with the request, if any.
+    pub fn from_request(&self, req: &Request) -> Option<String> {
+        cookie_value(req, COOKIE_NAME)
+    }
+
+    /// Creates a new, hard to guess session id.
+    pub fn create(&self) -> String {
+        let mut id = String::new();
+        for _ in 0..2 {
+            let mut hasher = self.seed.build_hasher();
+            self.counter
+                .fetch_add(1, Ordering::SeqCst)
+                .hash(&mut hasher);
+            if let Ok(t) = SystemTime::now().duration_since(UNIX_EPOCH) {
+                t.subsec_nanos().hash(&mut hasher);
+            }
+            id.push_str(&format!("{:016x}", hasher.finish()));
+        }
+        id
+    }
+
+    pub fn cookie(id: &str) -> Vec<u8> {
+        format!("{}={}; Path=/; HttpOnly", COOKIE_NAME, id).into_bytes()
+    }
+
+    pub fn record(&self, id: &str, name: &str, file: FileId) {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads
+            .entry(id.to_string())
+            .or_insert_with(Vec::new)
+            .push((name.to_string(), file));
+    }
+
+    /// Whether the session uploaded `file`, which is called `name` now.
+    pub fn owns(&self, id: &str, name: &str, file: FileId) -> bool {
+        match self.uploads.lock().unwrap().get(id) {
+            Some(files) => files.iter().any(|&(ref n, f)| n == name && f == file),
+            None => false,
+        }
+    }
+
+    /// Forgets the file called `name`, which was deleted.
+    pub fn forget(&self, name: &str) {
+        for files in self.uploads.lock().unwrap().values_mut() {
+            files.retain(|&(ref n, _)| n != name);
+        }
+    }
+}
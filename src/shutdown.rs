@@ -0,0 +1,272 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Whether the thread is in the middle of a request.
+    static IN_REQUEST: Cell<bool> = Cell::new(false);
+}
+
+/// Conditions under which the server stops on its own.
+pub struct ShutdownPolicy {
+    pub deadline: Option<Duration>,
+    pub idle: Option<Duration>,
+    pub max_downloads: Option<usize>,
+}
+
+pub enum Reason {
+    Deadline,
+    Idle,
+    Downloads,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reason::Deadline => write!(f, "time limit reached"),
+            Reason::Idle => write!(f, "idle for too long"),
+            Reason::Downloads => write!(f, "download limit reached"),
+        }
+    }
+}
+
+/// Request and transfer counters shared between handlers.
+pub struct Activity {
+    started: Instant,
+    last_seen: Mutex<Instant>,
+    closing: AtomicBool,
+    in_flight: Mutex<usize>,
+    /// Signalled whenever a request finishes.
+    finished: Condvar,
+    requests: AtomicUsize,
+    downloads_reserved: AtomicUsize,
+    downloads: AtomicUsize,
+    uploads: AtomicUsize,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    max_downloads: Option<usize>,
+}
+
+/// Marks a request as in progress until dropped.
+pub struct InFlight<'a>(&'a Activity);
+
+/// A reserved download. Dropping it without calling `complete`
+/// gives the reservation back.
+pub struct DownloadSlot<'a> {
+    activity: &'a Activity,
+    done: bool,
+}
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        IN_REQUEST.with(|r| r.set(false));
+        *self.0.in_flight.lock().unwrap() -= 1;
+        self.0.finished.notify_all();
+        self.0.touch();
+    }
+}
+
+impl<'a> DownloadSlot<'a> {
+    pub fn complete(mut self, bytes: u64) {
+        self.done = true;
+        self.activity.downloads.fetch_add(1, Ordering::SeqCst);
+        self.activity.bytes_sent.fetch_add(bytes, Ordering::SeqCst);
+    }
+}
+
+impl<'a> Drop for DownloadSlot<'a> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.activity
+                .downloads_reserved
+                .fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Activity {
+    pub fn new(max_downloads: Option<usize>) -> Activity {
+        let now = Instant::now();
+        Activity {
+            started: now,
+            last_seen: Mutex::new(now),
+            closing: AtomicBool::new(false),
+            in_flight: Mutex::new(0),
+            finished: Condvar::new(),
+            requests: AtomicUsize::new(0),
+            downloads_reserved: AtomicUsize::new(0),
+            downloads: AtomicUsize::new(0),
+            uploads: AtomicUsize::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            max_downloads,
+        }
+    }
+
+    pub fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    pub fn begin(&self) -> InFlight {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        *self.in_flight.lock().unwrap() += 1;
+        IN_REQUEST.with(|r| r.set(true));
+        self.touch();
+        InFlight(self)
+    }
+
+    /// Returns `None` once the download limit has been used up.
+    pub fn reserve_download(&self) -> Option<DownloadSlot> {
+        let reserved = self.downloads_reserved.fetch_add(1, Ordering::SeqCst);
+        if let Some(max) = self.max_downloads {
+            if reserved >= max {
+                self.downloads_reserved.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+        }
+        Some(DownloadSlot {
+            activity: self,
+            done: false,
+        })
+    }
+
+    pub fn record_upload(&self, bytes: u64) {
+        self.uploads.fetch_add(1, Ordering::SeqCst);
+        self.bytes_received.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn check(&self, policy: &ShutdownPolicy) -> Option<Reason> {
+        if let Some(deadline) = policy.deadline {
+            if self.started.elapsed() >= deadline {
+                return Some(Reason::Deadline);
+            }
+        }
+        if let Some(idle) = policy.idle {
+            if self.last_seen.lock().unwrap().elapsed() >= idle
+                && *self.in_flight.lock().unwrap() == 0
+            {
+                return Some(Reason::Idle);
+            }
+        }
+        if let Some(max) = policy.max_downloads {
+            if self.downloads.load(Ordering::SeqCst) >= max {
+                return Some(Reason::Downloads);
+            }
+        }
+        None
+    }
+
+    /// Blocks until one of the policy conditions is met and every
+    /// in-flight request has finished.
+    pub fn wait(&self, policy: &ShutdownPolicy) -> Reason {
+        let reason = loop {
+            if let Some(r) = self.check(policy) {
+                break r;
+            }
+            thread::sleep(Duration::from_millis(500));
+        };
+        self.close();
+        reason
+    }
+
+    /// Refuses new requests and waits for the ones in flight to finish.
+    /// Called while handling a request, as from a hook, it waits for the
+    /// others, since its own can't finish before it returns.
+    pub fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        let own = if IN_REQUEST.with(|r| r.get()) { 1 } else { 0 };
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight > own {
+            in_flight = self.finished.wait(in_flight).unwrap();
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "Served {} requests in {}s: {} downloads ({} bytes sent), \
+             {} uploads ({} bytes received)",
+            self.requests.load(Ordering::SeqCst),
+            self.started.elapsed().as_secs(),
+            self.downloads.load(Ordering::SeqCst),
+            self.bytes_sent.load(Ordering::SeqCst),
+            self.uploads.load(Ordering::SeqCst),
+            self.bytes_received.load(Ordering::SeqCst)
+        )
+    }
+}
+
+/// Parses durations such as "90", "45s", "30m", "2h" or "1h30m".
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut total: u64 = 0;
+    let mut num = String::new();
+
+    if s.is_empty() {
+        return Err("Empty duration".to_string());
+    }
+    for c in s.chars() {
+        if c.is_digit(10) {
+            num.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            _ => return Err(format!("Invalid duration \"{}\"", s)),
+        };
+        let n: u64 = match num.parse() {
+            Ok(n) => n,
+            Err(_) => return Err(format!("Invalid duration \"{}\"", s)),
+        };
+        total = match n.checked_mul(unit).and_then(|t| total.checked_add(t)) {
+            Some(t) => t,
+            None => return Err(format!("Duration \"{}\" is too long", s)),
+        };
+        num.clear();
+    }
+    if !num.is_empty() {
+        total = match num.parse::<u64>().ok().map(|n| total.checked_add(n)) {
+            Some(Some(t)) => t,
+            Some(None) => return Err(format!("Duration \"{}\" is too long", s)),
+            None => return Err(format!("Invalid duration \"{}\"", s)),
+        };
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Parses how long the server runs for, which can't be nothing.
+pub fn parse_timeout(s: &str) -> Result<Duration, String> {
+    let timeout = parse_duration(s)?;
+    if timeout.as_secs() == 0 {
+        return Err(format!("Timeout \"{}\" has to be longer than 0", s));
+    }
+    Ok(timeout)
+}
@@ -0,0 +1,90 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Largest snippet that will be accepted, in bytes.
+pub const MAX_SNIPPET_SIZE: u64 = 1024 * 1024;
+
+#[derive(Clone, RustcDecodable, RustcEncodable)]
+pub struct Snippet {
+    pub id: usize,
+    pub url: String,
+    pub text: String,
+    pub created: u64,
+    pub expires: Option<u64>,
+}
+
+/// Short pieces of text shared next to the files. Snippets are kept in
+/// memory only and are gone once na exits.
+pub struct Snippets {
+    entries: Mutex<Vec<Snippet>>,
+    next_id: Mutex<usize>,
+}
+
+fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+impl Snippets {
+    pub fn new() -> Snippets {
+        Snippets {
+            entries: Mutex::new(Vec::new()),
+            next_id: Mutex::new(1),
+        }
+    }
+
+    pub fn add(&self, text: String, ttl: Option<Duration>) -> Snippet {
+        let mut next_id = self.next_id.lock().unwrap();
+        let created = now();
+        let snippet = Snippet {
+            id: *next_id,
+            url: format!("/snippets/{}", *next_id),
+            text,
+            created,
+            expires: ttl.map(|t| created + t.as_secs()),
+        };
+        *next_id += 1;
+        self.entries.lock().unwrap().push(snippet.clone());
+        snippet
+    }
+
+    /// Returns all snippets that haven't expired yet, newest first.
+    pub fn list(&self) -> Vec<Snippet> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now();
+        entries.retain(|s| s.expires.map_or(true, |e| e > now));
+        entries.iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, id: usize) -> Option<Snippet> {
+        self.list().into_iter().find(|s| s.id == id)
+    }
+
+    pub fn remove(&self, id: usize) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let len = entries.len();
+        entries.retain(|s| s.id != id);
+        entries.len() != len
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use hyper::mime::Mime;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use static_r::Resource;
+
+/// Looks up the index template and the static assets served under
+/// `/resource/`. Files in the theme directory take precedence over the
+/// ones built into the binary.
+pub struct Theme {
+    dir: Option<PathBuf>,
+    embedded: Resource,
+}
+
+impl Theme {
+    pub fn new(dir: Option<PathBuf>) -> Theme {
+        Theme {
+            dir: dir,
+            embedded: Resource::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Cow<'static, [u8]>> {
+        if !is_asset_name(name) {
+            return None;
+        }
+        if let Some(ref dir) = self.dir {
+            if let Some(contents) = read_file(&dir.join(name)) {
+                return Some(Cow::Owned(contents));
+            }
+        }
+        self.embedded.r.get(name).map(|r| Cow::Borrowed(*r))
+    }
+
+    /// A page template such as `index.html`. A broken override falls
+    /// back to the built-in one rather than taking the page down.
+    pub fn template(&self, name: &str) -> String {
+        if let Some(t) = self.get(name) {
+            if let Ok(t) = String::from_utf8(t.into_owned()) {
+                return t;
+            }
+        }
+        String::from_utf8(self.embedded.r[name].to_vec()).unwrap()
+    }
+}
+
+/// Asset names are plain file names, anything that could point outside
+/// of the theme directory is refused.
+fn is_asset_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains('/') && !name.contains('\\')
+}
+
+fn read_file(path: &Path) -> Option<Vec<u8>> {
+    if !path.is_file() {
+        return None;
+    }
+    let mut contents = Vec::new();
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => Some(contents),
+        Err(_) => None,
+    }
+}
+
+pub fn content_type(name: &str) -> Mime {
+    let extension = match name.rfind('.') {
+        Some(i) => name[i + 1..].to_lowercase(),
+        None => String::new(),
+    };
+    let mime = match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "atom" => "application/atom+xml; charset=utf-8",
+        "m3u" | "m3u8" => "audio/x-mpegurl; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        _ => "application/octet-stream",
+    };
+    mime.parse().unwrap()
+}
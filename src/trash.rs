@@ -0,0 +1,214 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use directory::Directory;
+use locale::Style;
+
+/// Hidden directory inside the served one that deleted files are moved
+/// to. Its name starts with `directory::RESERVED_PREFIX`, so no upload can
+/// take it.
+pub const TRASH_DIR: &'static str = ".na-trash";
+
+/// How long deleted files are kept unless told otherwise.
+pub const DEFAULT_RETENTION: u64 = 7 * 24 * 60 * 60;
+
+/// Deleted files, each kept as `.na-trash/<id>/<name>` until it's
+/// restored, purged or older than the retention period.
+pub struct Trash {
+    dir: PathBuf,
+    retention: Duration,
+    seed: RandomState,
+    counter: AtomicUsize,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct TrashEntry {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub deleted: String,
+    pub deleted_raw: u64,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Restored {
+    pub name: String,
+    pub restored_name: String,
+}
+
+/// A file removed from the trash for good.
+pub struct Purged {
+    pub name: String,
+    pub size: u64,
+}
+
+impl Trash {
+    pub fn new(root: &Path, retention: Duration) -> Trash {
+        Trash {
+            dir: root.join(TRASH_DIR),
+            retention: retention,
+            seed: RandomState::new(),
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    /// Ids can't be guessed, so handing one out is what allows undoing a
+    /// delete.
+    fn new_id(&self) -> String {
+        let mut hasher = self.seed.build_hasher();
+        self.counter
+            .fetch_add(1, Ordering::SeqCst)
+            .hash(&mut hasher);
+        if let Ok(t) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            t.hash(&mut hasher);
+        }
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn entry_dir(&self, id: &str) -> Option<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_digit(16)) {
+            return None;
+        }
+        Some(self.dir.join(id))
+    }
+
+    /// Moves the file at `path` into the trash and returns its id.
+    pub fn put(&self, path: &Path, name: &str) -> io::Result<String> {
+        self.purge_expired();
+        let id = self.new_id();
+        let entry = self.dir.join(&id);
+        fs::create_dir_all(&entry)?;
+        if let Err(e) = fs::rename(path, entry.join(name)) {
+            let _ = fs::remove_dir(&entry);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Everything in the trash, most recently deleted first, with dates
+    /// shown in `style`.
+    pub fn list(&self, style: &Style) -> Vec<TrashEntry> {
+        self.purge_expired();
+        let mut entries: Vec<TrashEntry> = self
+            .ids()
+            .iter()
+            .filter_map(|id| self.entry(id, style))
+            .collect();
+        entries.sort_by(|a, b| b.deleted_raw.cmp(&a.deleted_raw));
+        entries
+    }
+
+    fn ids(&self) -> Vec<String> {
+        match fs::read_dir(&self.dir) {
+            Ok(dirs) => dirs
+                .filter_map(|d| d.ok())
+                .map(|d| d.file_name().to_string_lossy().to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// The deleted file kept under `id`.
+    fn stored(&self, id: &str) -> Option<fs::DirEntry> {
+        let dir = self.entry_dir(id)?;
+        fs::read_dir(&dir).ok()?.filter_map(|f| f.ok()).next()
+    }
+
+    fn entry(&self, id: &str, style: &Style) -> Option<TrashEntry> {
+        let dir = self.entry_dir(id)?;
+        let deleted = fs::metadata(&dir).and_then(|m| m.modified()).ok()?;
+        let deleted_raw = deleted
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let file = self.stored(id)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Some(TrashEntry {
+            id: id.to_string(),
+            name: file.file_name().to_string_lossy().to_string(),
+            size: size,
+            deleted: style.timestamp(deleted_raw),
+            deleted_raw: deleted_raw,
+        })
+    }
+
+    /// Moves a deleted file back into the served directory, under a new
+    /// name if the old one has been taken in the meantime.
+    pub fn restore(&self, id: &str, directory: &Directory) -> io::Result<Restored> {
+        let name = match self.stored(id) {
+            Some(f) => f.file_name().to_string_lossy().to_string(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such entry")),
+        };
+        let dir = self.dir.join(id);
+        let restored = directory.commit_partial(&dir.join(&name), &name, false)?;
+        let _ = fs::remove_dir_all(&dir);
+        Ok(Restored {
+            name: name,
+            restored_name: restored,
+        })
+    }
+
+    pub fn purge(&self, id: &str) -> io::Result<Purged> {
+        let file = match self.stored(id) {
+            Some(f) => f,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such entry")),
+        };
+        let purged = Purged {
+            name: file.file_name().to_string_lossy().to_string(),
+            size: file.metadata().map(|m| m.len()).unwrap_or(0),
+        };
+        fs::remove_dir_all(self.dir.join(id))?;
+        Ok(purged)
+    }
+
+    /// Empties the trash and returns the files removed.
+    pub fn purge_all(&self) -> Vec<Purged> {
+        self.ids()
+            .iter()
+            .filter_map(|id| self.purge(id).ok())
+            .collect()
+    }
+
+    /// Removes whatever has been in the trash for longer than the
+    /// retention period.
+    pub fn purge_expired(&self) {
+        let dirs = match fs::read_dir(&self.dir) {
+            Ok(d) => d,
+            Err(_) => return,
+        };
+        for d in dirs.filter_map(|d| d.ok()) {
+            let age = d
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|m| m.elapsed().ok());
+            if age.map_or(false, |a| a >= self.retention) {
+                let _ = fs::remove_dir_all(d.path());
+            }
+        }
+    }
+}
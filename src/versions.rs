@@ -0,0 +1,248 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, UNIX_EPOCH};
+
+use locale::Style;
+
+/// Hidden directory inside the served one that older revisions are kept
+/// in. Its name starts with `directory::RESERVED_PREFIX`, so no upload can
+/// take it.
+pub const VERSIONS_DIR: &'static str = ".na-versions";
+
+/// How many older revisions of a file are kept unless told otherwise.
+pub const DEFAULT_KEEP: usize = 10;
+
+/// Which older revisions to keep.
+pub struct VersionPolicy {
+    /// Keep at most this many older revisions per file.
+    pub keep: Option<usize>,
+    /// Drop revisions that were uploaded longer ago than this.
+    pub max_age: Option<Duration>,
+}
+
+/// Older revisions of files, kept as `.na-versions/<name>/<n>`. Revisions
+/// are numbered from 1 and the file itself is always the newest one. The
+/// number the file itself has is kept in `.na-versions/<name>/current`, so
+/// that numbering carries on after every older revision has been pruned.
+pub struct Versions {
+    dir: PathBuf,
+    policy: VersionPolicy,
+    lock: Mutex<()>,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Revision {
+    pub version: u64,
+    pub size: u64,
+    pub modified: String,
+    pub modified_raw: u64,
+    pub current: bool,
+}
+
+impl Versions {
+    pub fn new(root: &Path, policy: VersionPolicy) -> Versions {
+        Versions {
+            dir: root.join(VERSIONS_DIR),
+            policy: policy,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Held while a file is being replaced, so that two uploads of the
+    /// same name don't archive into the same revision.
+    pub fn lock(&self) -> MutexGuard<()> {
+        self.lock.lock().unwrap()
+    }
+
+    fn file_dir(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn numbers(&self, name: &str) -> Vec<u64> {
+        let mut numbers: Vec<u64> = match fs::read_dir(self.file_dir(name)) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse().ok()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        numbers.sort();
+        numbers
+    }
+
+    /// The number the file itself currently has.
+    fn current_number(&self, name: &str) -> u64 {
+        let mut text = String::new();
+        let stored = fs::File::open(self.file_dir(name).join("current"))
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .ok()
+            .and_then(|_| text.trim().parse().ok());
+        // Stores written before the number was kept only have the
+        // revisions to go by.
+        stored.unwrap_or_else(|| self.numbers(name).last().map_or(1, |n| n + 1))
+    }
+
+    fn set_current_number(&self, name: &str, number: u64) -> io::Result<()> {
+        fs::write(self.file_dir(name).join("current"), format!("{}\n", number))
+    }
+
+    /// Keeps the file at `current`, if there is one, as the newest older
+    /// revision. The file itself stays where it is, so that it can be
+    /// replaced in one step afterwards; the revision is a hard link to it,
+    /// or a copy where links aren't supported. Returns the revision, to be
+    /// handed to `unarchive` if replacing the file fails. Call with the
+    /// lock held.
+    pub fn archive(&self, current: &Path, name: &str) -> io::Result<Option<u64>> {
+        if !current.is_file() {
+            return Ok(None);
+        }
+        let dir = self.file_dir(name);
+        fs::create_dir_all(&dir)?;
+        let number = self.current_number(name);
+        let path = dir.join(number.to_string());
+        if fs::hard_link(current, &path).is_err() {
+            fs::copy(current, &path)?;
+        }
+        if let Err(e) = self.set_current_number(name, number + 1) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(Some(number))
+    }
+
+    /// Undoes `archive` after the file couldn't be replaced.
+    pub fn unarchive(&self, name: &str, archived: Option<u64>) {
+        if let Some(number) = archived {
+            let _ = fs::remove_file(self.file_dir(name).join(number.to_string()));
+            let _ = self.set_current_number(name, number);
+        }
+    }
+
+    /// Where revision `version` of the file at `current` is, be it the
+    /// file itself or an older one.
+    pub fn resolve(&self, name: &str, version: u64, current: &Path) -> Option<PathBuf> {
+        if version == self.current_number(name) && current.is_file() {
+            return Some(current.to_path_buf());
+        }
+        self.path(name, version)
+    }
+
+    /// Where revision `version` of a file is stored, if it's an older one.
+    pub fn path(&self, name: &str, version: u64) -> Option<PathBuf> {
+        let path = self.file_dir(name).join(version.to_string());
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Every revision of a file, newest first, including the file itself
+    /// at `current`. Dates are shown in `style`.
+    pub fn list(&self, name: &str, current: &Path, style: &Style) -> Vec<Revision> {
+        self.prune(name);
+        let mut revisions = Vec::new();
+        if let Ok(meta) = fs::metadata(current) {
+            revisions.push(revision(self.current_number(name), &meta, true, style));
+        }
+        for n in self.numbers(name).into_iter().rev() {
+            if let Ok(meta) = fs::metadata(self.file_dir(name).join(n.to_string())) {
+                revisions.push(revision(n, &meta, false, style));
+            }
+        }
+        revisions
+    }
+
+    /// Copies an older revision over the file, which is archived in turn.
+    pub fn restore(&self, name: &str, version: u64, current: &Path) -> io::Result<()> {
+        let _guard = self.lock();
+        let old = match self.path(name, version) {
+            Some(p) => p,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such version")),
+        };
+        // Copy next to the file first, so that it's replaced in one step.
+        let copy = self.file_dir(name).join("restoring");
+        fs::copy(&old, &copy)?;
+        let archived = match self.archive(current, name) {
+            Ok(a) => a,
+            Err(e) => {
+                let _ = fs::remove_file(&copy);
+                return Err(e);
+            }
+        };
+        if let Err(e) = fs::rename(&copy, current) {
+            let _ = fs::remove_file(&copy);
+            self.unarchive(name, archived);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Drops every older revision of a file that was deleted, so that a
+    /// new file of the same name starts its own history.
+    pub fn forget(&self, name: &str) {
+        let _guard = self.lock();
+        let _ = fs::remove_dir_all(self.file_dir(name));
+    }
+
+    /// Drops the revisions of a file that the policy doesn't keep.
+    pub fn prune(&self, name: &str) {
+        let numbers = self.numbers(name);
+        let dir = self.file_dir(name);
+        let excess = match self.policy.keep {
+            Some(keep) if numbers.len() > keep => numbers.len() - keep,
+            _ => 0,
+        };
+        for (i, n) in numbers.iter().enumerate() {
+            let path = dir.join(n.to_string());
+            let expired = match self.policy.max_age {
+                Some(max_age) => fs::metadata(&path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|m| m.elapsed().ok())
+                    .map_or(false, |age| age >= max_age),
+                None => false,
+            };
+            if i < excess || expired {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+}
+
+fn revision(version: u64, meta: &fs::Metadata, current: bool, style: &Style) -> Revision {
+    let modified_raw = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Revision {
+        version: version,
+        size: meta.len(),
+        modified: style.timestamp(modified_raw),
+        modified_raw: modified_raw,
+        current: current,
+    }
+}
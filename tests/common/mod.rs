@@ -0,0 +1,243 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Starts na on an ephemeral port over a temporary directory and talks
+//! to it over HTTP.
+
+#![allow(dead_code)]
+
+use na::hyper::client::{Body, Client, RedirectPolicy};
+use na::hyper::header::Headers;
+use na::hyper::method::Method;
+use na::hyper::status::StatusCode;
+use na::{Directory, ServerBuilder, ServerHandle};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use tempdir::TempDir;
+
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Headers that send back the cookie set by the reply, to make more
+    /// requests in the same session.
+    pub fn session(&self) -> Headers {
+        let cookie =
+            String::from_utf8(self.headers.get_raw("Set-Cookie").unwrap()[0].clone()).unwrap();
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "Cookie",
+            vec![cookie.split(';').next().unwrap().as_bytes().to_vec()],
+        );
+        headers
+    }
+}
+
+pub struct TestServer {
+    pub dir: TempDir,
+    pub base: String,
+    handle: Option<ServerHandle>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::with(|b| b)
+    }
+
+    /// Starts a server configured by `configure`, which receives a
+    /// builder that is already bound to an ephemeral port.
+    pub fn with<F>(configure: F) -> TestServer
+    where
+        F: FnOnce(ServerBuilder) -> ServerBuilder,
+    {
+        TestServer::configured(|_| {}, configure)
+    }
+
+    /// Starts a server whose `Directory` was adjusted by `configure`.
+    pub fn start_in<F>(configure: F) -> TestServer
+    where
+        F: FnOnce(&mut Directory),
+    {
+        TestServer::configured(configure, |b| b)
+    }
+
+    fn configured<D, F>(directory: D, configure: F) -> TestServer
+    where
+        D: FnOnce(&mut Directory),
+        F: FnOnce(ServerBuilder) -> ServerBuilder,
+    {
+        let dir = TempDir::new("na-test").unwrap();
+        let mut shared = Directory::new(dir.path().to_path_buf());
+        directory(&mut shared);
+        let builder = ServerBuilder::new(shared).bind("127.0.0.1:0");
+        let handle = configure(builder).start().unwrap();
+        let base = format!("http://{}", handle.addr());
+        TestServer {
+            dir,
+            base,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn write(&self, name: &str, contents: &[u8]) {
+        File::create(self.path(name))
+            .unwrap()
+            .write_all(contents)
+            .unwrap();
+    }
+
+    pub fn read(&self, name: &str) -> Vec<u8> {
+        let mut contents = Vec::new();
+        File::open(self.path(name))
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        fs::metadata(self.path(name)).is_ok()
+    }
+
+    pub fn file_url(name: &str) -> String {
+        format!("/files/{}", utf8_percent_encode(name, NON_ALPHANUMERIC))
+    }
+
+    pub fn request(&self, method: Method, path: &str, headers: Headers, body: &[u8]) -> Reply {
+        let mut client = Client::new();
+        client.set_redirect_policy(RedirectPolicy::FollowNone);
+        let url = format!("{}{}", self.base, path);
+        let mut res = client
+            .request(method, &*url)
+            .headers(headers)
+            .body(Body::BufBody(body, body.len()))
+            .send()
+            .unwrap();
+        let mut reply = Vec::new();
+        res.read_to_end(&mut reply).unwrap();
+        Reply {
+            status: res.status,
+            headers: res.headers.clone(),
+            body: reply,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Reply {
+        self.request(Method::Get, path, Headers::new(), b"")
+    }
+
+    pub fn delete(&self, path: &str) -> Reply {
+        self.request(Method::Delete, path, Headers::new(), b"")
+    }
+
+    pub fn put(&self, path: &str, headers: Headers, body: &[u8]) -> Reply {
+        self.request(Method::Put, path, headers, body)
+    }
+
+    /// Sends the body with chunked transfer encoding instead of a
+    /// Content-Length.
+    pub fn put_chunked(&self, path: &str, mut body: &[u8]) -> Reply {
+        let url = format!("{}{}", self.base, path);
+        let mut res = Client::new()
+            .request(Method::Put, &*url)
+            .body(Body::ChunkedBody(&mut body))
+            .send()
+            .unwrap();
+        let mut reply = Vec::new();
+        res.read_to_end(&mut reply).unwrap();
+        Reply {
+            status: res.status,
+            headers: res.headers.clone(),
+            body: reply,
+        }
+    }
+
+    /// Uploads files through the multipart form, like the browser does.
+    pub fn upload(&self, files: &[(&str, &[u8])]) -> Reply {
+        let fields: Vec<(&str, Option<&str>, &[u8])> = files
+            .iter()
+            .map(|&(name, contents)| ("upload[]", Some(name), contents))
+            .collect();
+        self.post_form(&fields)
+    }
+
+    /// Posts a multipart form made of (field, file name, contents).
+    /// Fields without a file name are sent as text.
+    pub fn post_form(&self, fields: &[(&str, Option<&str>, &[u8])]) -> Reply {
+        self.post_form_with(Headers::new(), fields)
+    }
+
+    /// Like `post_form`, with extra headers.
+    pub fn post_form_with(
+        &self,
+        mut headers: Headers,
+        fields: &[(&str, Option<&str>, &[u8])],
+    ) -> Reply {
+        let boundary = "------------------------natestboundary";
+        let mut body: Vec<u8> = Vec::new();
+        for &(field, filename, contents) in fields {
+            let disposition = match filename {
+                Some(f) => format!(
+                    "form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream",
+                    field, f
+                ),
+                None => format!("form-data; name=\"{}\"", field),
+            };
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: {}\r\n\r\n",
+                    boundary, disposition
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(contents);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        headers.set_raw(
+            "Content-Type",
+            vec![format!("multipart/form-data; boundary={}", boundary).into_bytes()],
+        );
+        self.request(Method::Post, "/", headers, &body)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.shutdown();
+        }
+    }
+}
@@ -0,0 +1,1944 @@
+/*
+ * na
+ *
+ * Copyright (C) 2016 Srđan Panić <sp@srdja.me>
+ *
+ * na is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * na is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with na.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate na;
+extern crate percent_encoding;
+extern crate rustc_serialize;
+extern crate tempdir;
+
+mod common;
+
+use common::TestServer;
+use na::api;
+use na::client;
+use na::directory::FileMeta;
+use na::hyper::header::Headers;
+use na::hyper::method::Method;
+use na::hyper::status::StatusCode;
+use na::locale::{self, Units};
+use na::routes::{ErrorReply, UploadReport};
+use na::SavedFile;
+use rustc_serialize::json::{self, Json};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[test]
+fn list_shows_files() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+    server.write("b.txt", b"b");
+
+    let reply = server.get("/list");
+    assert_eq!(reply.status, StatusCode::Ok);
+    let text = reply.text();
+    assert!(text.contains("/files/a.txt\n"));
+    assert!(text.contains("/files/b.txt\n"));
+}
+
+#[test]
+fn json_lists_file_metadata() {
+    let server = TestServer::start();
+    server.write("a.txt", b"hello");
+
+    let reply = server.get("/json");
+    assert_eq!(reply.status, StatusCode::Ok);
+    let files: Vec<FileMeta> = json::decode(&reply.text()).unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "a.txt");
+    assert_eq!(files[0].url, "/files/a.txt");
+    assert_eq!(files[0].size, 5);
+}
+
+#[test]
+fn index_renders_file_table() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+
+    let reply = server.get("/");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(reply.text().contains("href=\"/files/a.txt\""));
+}
+
+#[test]
+fn download_returns_contents() {
+    let server = TestServer::start();
+    server.write("a.txt", b"some contents");
+
+    let reply = server.get("/files/a.txt");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(reply.body, b"some contents".to_vec());
+}
+
+#[test]
+fn download_large_file() {
+    let server = TestServer::start();
+    let contents: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    server.write("large.bin", &contents);
+
+    let reply = server.get("/files/large.bin");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(reply.body.len(), contents.len());
+    assert!(reply.body == contents);
+}
+
+#[test]
+fn download_range() {
+    let server = TestServer::start();
+    server.write("a.txt", b"0123456789");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Range", vec![b"bytes=4-".to_vec()]);
+    let reply = server.request(Method::Get, "/files/a.txt", headers, b"");
+    assert_eq!(reply.status, StatusCode::PartialContent);
+    assert_eq!(reply.body, b"456789".to_vec());
+}
+
+#[test]
+fn head_sends_the_digest_without_counting_a_download() {
+    let server = TestServer::with(|b| b.max_downloads(Some(1)));
+    server.write("a.txt", b"0123456789");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Want-Repr-Digest", vec![b"sha-256=10".to_vec()]);
+    let reply = server.request(Method::Head, "/files/a.txt", headers, b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(reply.headers.get_raw("Repr-Digest").is_some());
+    assert!(reply.body.is_empty());
+
+    assert_eq!(server.get("/files/a.txt").body, b"0123456789".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt").status,
+        StatusCode::ServiceUnavailable
+    );
+}
+
+#[test]
+fn download_unicode_and_percent_encoded_names() {
+    let server = TestServer::start();
+    for name in &["ünïcødé.txt", "with space.txt", "100%.txt", "日本語"] {
+        server.write(name, name.as_bytes());
+
+        let reply = server.get(&TestServer::file_url(name));
+        assert_eq!(reply.status, StatusCode::Ok, "{}", name);
+        assert_eq!(reply.body, name.as_bytes().to_vec());
+    }
+}
+
+#[test]
+fn download_missing_file_is_404() {
+    let server = TestServer::start();
+
+    let reply = server.get("/files/nothing-here");
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[test]
+fn download_of_a_vanished_file_is_404() {
+    let server = TestServer::with(|b| b.index(Some(std::time::Duration::from_secs(3600))));
+    server.write("a.txt", b"a");
+    assert_eq!(server.get("/list").text(), "/files/a.txt\n");
+
+    // Gone before the index noticed.
+    fs::remove_file(server.path("a.txt")).unwrap();
+    assert_eq!(server.get("/files/a.txt").status, StatusCode::NotFound);
+    let reply = server.get("/api/v1/files/a.txt/content");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    let error: ErrorReply = json::decode(&reply.text()).unwrap();
+    assert_eq!(error.error.code, "not_found");
+}
+
+#[test]
+fn unknown_path_is_404() {
+    let server = TestServer::start();
+
+    let reply = server.get("/no/such/thing");
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[test]
+fn unsupported_method_is_405() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+
+    let reply = server.request(Method::Patch, "/files/a.txt", Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::MethodNotAllowed);
+}
+
+#[test]
+fn upload_saves_file() {
+    let server = TestServer::start();
+
+    let reply = server.upload(&[("new.txt", b"uploaded")]);
+    assert_eq!(reply.status, StatusCode::Found);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].source_name, "new.txt");
+    assert_eq!(saved[0].saved_name, "new.txt");
+    assert_eq!(server.read("new.txt"), b"uploaded".to_vec());
+}
+
+#[test]
+fn upload_renames_on_collision() {
+    let server = TestServer::start();
+    server.write("a.txt", b"original");
+
+    let reply = server.upload(&[("a.txt", b"first"), ("a.txt", b"second")]);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved[0].saved_name, "a.txt (1)");
+    assert_eq!(saved[1].saved_name, "a.txt (2)");
+    assert_eq!(server.read("a.txt"), b"original".to_vec());
+    assert_eq!(server.read("a.txt (1)"), b"first".to_vec());
+    assert_eq!(server.read("a.txt (2)"), b"second".to_vec());
+}
+
+#[test]
+fn upload_overwrites_when_enabled() {
+    let server = TestServer::with(|b| b.overwrite(true));
+    server.write("a.txt", b"original");
+
+    let reply = server.upload(&[("a.txt", b"replaced")]);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved[0].saved_name, "a.txt");
+    assert_eq!(server.read("a.txt"), b"replaced".to_vec());
+}
+
+#[test]
+fn upload_disabled_is_405() {
+    let server = TestServer::with(|b| b.enable_upload(false));
+
+    let reply = server.upload(&[("a.txt", b"a")]);
+    assert_eq!(reply.status, StatusCode::MethodNotAllowed);
+    assert!(!server.exists("a.txt"));
+}
+
+#[test]
+fn delete_removes_file_when_enabled() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("a.txt", b"a");
+
+    let reply = server.delete("/files/a.txt");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(!server.exists("a.txt"));
+}
+
+#[test]
+fn delete_ignores_the_query_and_bad_encoding() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("a b.txt", b"a");
+
+    let reply = server.delete("/files/a%20b.txt?token=x");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(!server.exists("a b.txt"));
+
+    let reply = server.delete("/files/%FF");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    assert_eq!(server.get("/resource/%FF").status, StatusCode::NotFound);
+}
+
+#[test]
+fn delete_is_405_when_disabled() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+
+    let reply = server.delete("/files/a.txt");
+    assert_eq!(reply.status, StatusCode::MethodNotAllowed);
+    assert!(server.exists("a.txt"));
+}
+
+#[test]
+fn delete_missing_file_is_404() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+
+    let reply = server.delete("/files/nothing-here");
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[test]
+fn token_is_required_when_set() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())));
+    server.write("a.txt", b"a");
+
+    assert_eq!(server.get("/list").status, StatusCode::Unauthorized);
+
+    let mut headers = Headers::new();
+    headers.set_raw("Authorization", vec![b"Bearer secret".to_vec()]);
+    let reply = server.request(Method::Get, "/list", headers, b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+}
+
+#[test]
+fn main_page_takes_the_token_in_the_query() {
+    let server = TestServer::with(|b| b.token(Some("a b&c".to_string())));
+
+    let reply = server.get("/?token=a%20b%26c");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(reply.headers.get_raw("Set-Cookie").is_some());
+    assert_eq!(server.get("/?token=a").status, StatusCode::Unauthorized);
+}
+
+#[test]
+fn embedded_binary_resource() {
+    let server = TestServer::start();
+
+    let reply = server.get("/favicon.ico");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(
+        reply.headers.get_raw("Content-Type").unwrap()[0],
+        b"image/x-icon".to_vec()
+    );
+    assert_eq!(&reply.body[0..4], &[0u8, 0, 1, 0][..]);
+}
+
+#[test]
+fn theme_dir_overrides_resources() {
+    let theme = tempdir::TempDir::new("na-theme").unwrap();
+    fs::write(theme.path().join("style.css"), "body { color: red; }").unwrap();
+    fs::write(theme.path().join("logo.png"), b"\x89PNG").unwrap();
+    fs::write(
+        theme.path().join("index.html"),
+        "<p>custom {{#files}}{{name}}{{/files}}</p>",
+    )
+    .unwrap();
+    let server = TestServer::with(|b| b.theme_dir(Some(theme.path().to_path_buf())));
+    server.write("a.txt", b"a");
+
+    assert_eq!(
+        server.get("/resource/style.css").text(),
+        "body { color: red; }"
+    );
+    let logo = server.get("/resource/logo.png");
+    assert_eq!(
+        logo.headers.get_raw("Content-Type").unwrap()[0],
+        b"image/png".to_vec()
+    );
+    assert_eq!(server.get("/").text(), "<p>custom a.txt</p>");
+    // Files the theme doesn't provide fall back to the built-in ones.
+    assert!(server
+        .get("/resource/script.js")
+        .text()
+        .contains("function"));
+    assert_eq!(
+        server.get("/resource/missing.css").status,
+        StatusCode::NotFound
+    );
+}
+
+#[test]
+fn index_renders_readme() {
+    let server = TestServer::start();
+    server.write("README.md", b"# Hello\n\n<script>alert(1)</script>\n");
+
+    let text = server.get("/").text();
+    assert!(text.contains("<h1>Hello</h1>"));
+    assert!(!text.contains("<script>alert(1)"));
+}
+
+#[test]
+fn markdown_render_query() {
+    let server = TestServer::start();
+    server.write("notes.md", b"*some* [link](javascript:alert(1))");
+
+    let reply = server.get("/files/notes.md?render=1");
+    assert_eq!(reply.status, StatusCode::Ok);
+    let text = reply.text();
+    assert!(text.contains("<em>some</em>"));
+    assert!(!text.contains("javascript:"));
+
+    // Without the parameter the file is downloaded as is.
+    assert_eq!(server.get("/files/notes.md").body, server.read("notes.md"));
+}
+
+#[test]
+fn put_saves_raw_body() {
+    let server = TestServer::start();
+
+    let reply = server.put("/files/raw.txt", Headers::new(), b"raw contents");
+    assert_eq!(reply.status, StatusCode::Created);
+    assert_eq!(
+        reply.headers.get_raw("Location").unwrap()[0],
+        b"/files/raw.txt".to_vec()
+    );
+    let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.saved_name, "raw.txt");
+    assert_eq!(server.read("raw.txt"), b"raw contents".to_vec());
+}
+
+#[test]
+fn put_chunked_body() {
+    let server = TestServer::start();
+
+    let reply = server.put_chunked("/files/chunked.txt", b"sent in chunks");
+    assert_eq!(reply.status, StatusCode::Created);
+    assert_eq!(server.read("chunked.txt"), b"sent in chunks".to_vec());
+}
+
+#[test]
+fn put_renames_on_collision() {
+    let server = TestServer::start();
+    server.write("a.txt", b"original");
+
+    let reply = server.put("/files/a.txt", Headers::new(), b"new");
+    let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.saved_name, "a.txt (1)");
+    assert_eq!(server.read("a.txt"), b"original".to_vec());
+}
+
+#[test]
+fn put_if_none_match_refuses_existing_file() {
+    let server = TestServer::with(|b| b.overwrite(true));
+    server.write("a.txt", b"original");
+
+    let mut headers = Headers::new();
+    headers.set_raw("If-None-Match", vec![b"*".to_vec()]);
+    let reply = server.put("/files/a.txt", headers.clone(), b"new");
+    assert_eq!(reply.status, StatusCode::PreconditionFailed);
+    assert_eq!(server.read("a.txt"), b"original".to_vec());
+
+    let reply = server.put("/files/b.txt", headers, b"new");
+    assert_eq!(reply.status, StatusCode::Created);
+}
+
+#[test]
+fn put_if_none_match_saves_only_one_of_concurrent_uploads() {
+    let server = TestServer::start();
+
+    let uploads: Vec<_> = (0..8)
+        .map(|i| {
+            let base = server.base.clone();
+            thread::spawn(move || {
+                let body = format!("upload {}", i);
+                let url = format!("{}/files/same.txt", base);
+                let mut headers = Headers::new();
+                headers.set_raw("If-None-Match", vec![b"*".to_vec()]);
+                na::hyper::Client::new()
+                    .put(&*url)
+                    .headers(headers)
+                    .body(&*body)
+                    .send()
+                    .unwrap()
+                    .status
+            })
+        })
+        .collect();
+    let statuses: Vec<StatusCode> = uploads.into_iter().map(|u| u.join().unwrap()).collect();
+    let created = statuses
+        .iter()
+        .filter(|s| **s == StatusCode::Created)
+        .count();
+    assert_eq!(created, 1);
+    assert!(statuses
+        .iter()
+        .all(|s| *s == StatusCode::Created || *s == StatusCode::PreconditionFailed));
+    assert_eq!(server.get("/list").text(), "/files/same.txt\n");
+}
+
+#[test]
+fn put_disabled_is_405() {
+    let server = TestServer::with(|b| b.enable_upload(false));
+
+    let reply = server.put("/files/a.txt", Headers::new(), b"a");
+    assert_eq!(reply.status, StatusCode::MethodNotAllowed);
+    assert!(!server.exists("a.txt"));
+}
+
+#[test]
+fn concurrent_uploads_of_the_same_name_are_kept() {
+    let server = TestServer::start();
+
+    let uploads: Vec<_> = (0..8)
+        .map(|i| {
+            let base = server.base.clone();
+            thread::spawn(move || {
+                let body = format!("upload {}", i);
+                let url = format!("{}/files/same.txt", base);
+                let mut res = na::hyper::Client::new()
+                    .put(&*url)
+                    .body(&*body)
+                    .send()
+                    .unwrap();
+                let mut reply = String::new();
+                res.read_to_string(&mut reply).unwrap();
+                let saved: SavedFile = json::decode(reply.trim()).unwrap();
+                saved.saved_name
+            })
+        })
+        .collect();
+    let mut names: Vec<String> = uploads.into_iter().map(|u| u.join().unwrap()).collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), 8);
+    for name in names {
+        assert!(server.exists(&name));
+    }
+}
+
+#[test]
+fn unfinished_uploads_are_hidden_and_cleaned_up() {
+    let dir = tempdir::TempDir::new("na-test").unwrap();
+    let stale = dir.path().join(".na-upload-1-1-1.part");
+    fs::write(&stale, b"half an upload").unwrap();
+    // Make it look like it was left behind a while ago.
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&stale)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    fs::write(dir.path().join(".na-upload-2-2-2.part"), b"in progress").unwrap();
+
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .start()
+        .unwrap();
+    assert!(!stale.exists());
+    assert!(dir.path().join(".na-upload-2-2-2.part").exists());
+    let listing = handle.state().directory.list_available_resources();
+    assert!(listing.is_empty());
+    handle.shutdown();
+}
+
+const HELLO_SHA256: &'static str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+const HELLO_SHA256_HEX: &'static str =
+    "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+#[test]
+fn upload_returns_sha256() {
+    let server = TestServer::start();
+
+    let reply = server.upload(&[("hello.txt", b"hello")]);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved[0].sha256, HELLO_SHA256_HEX);
+}
+
+#[test]
+fn put_checks_content_digest() {
+    let server = TestServer::start();
+
+    let mut headers = Headers::new();
+    let digest = format!("sha-256=:{}:", HELLO_SHA256);
+    headers.set_raw("Content-Digest", vec![digest.into_bytes()]);
+    let reply = server.put("/files/good.txt", headers.clone(), b"hello");
+    assert_eq!(reply.status, StatusCode::Created);
+    let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.sha256, HELLO_SHA256_HEX);
+
+    let reply = server.put("/files/bad.txt", headers, b"hellO");
+    assert_eq!(reply.status, StatusCode::BadRequest);
+    assert!(reply.text().contains("sha-256 mismatch"));
+    assert!(!server.exists("bad.txt"));
+    assert_eq!(fs::read_dir(server.dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn put_checks_content_md5() {
+    let server = TestServer::start();
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-MD5", vec![b"XUFAKrxLKna5cZ2REBfFkg==".to_vec()]);
+    let reply = server.put("/files/good.txt", headers.clone(), b"hello");
+    assert_eq!(reply.status, StatusCode::Created);
+
+    let reply = server.put("/files/bad.txt", headers, b"goodbye");
+    assert_eq!(reply.status, StatusCode::BadRequest);
+    assert!(!server.exists("bad.txt"));
+}
+
+#[test]
+fn upload_checks_digest_field() {
+    let server = TestServer::start();
+    let digest = format!("sha-256={}", HELLO_SHA256_HEX);
+
+    let reply = server.post_form(&[
+        ("digest", None, digest.as_bytes()),
+        ("upload[]", Some("good.txt"), b"hello"),
+    ]);
+    assert_eq!(reply.status, StatusCode::Found);
+    assert!(server.exists("good.txt"));
+
+    let reply = server.post_form(&[
+        ("digest", None, digest.as_bytes()),
+        ("upload[]", Some("bad.txt"), b"corrupted"),
+    ]);
+    assert_eq!(reply.status, StatusCode::BadRequest);
+    assert!(!server.exists("bad.txt"));
+
+    // A digest that doesn't parse only fails the file it belongs to.
+    let reply = server.post_form(&[
+        ("upload[]", Some("before.txt"), b"hello"),
+        ("digest", None, b"sha-256=nonsense"),
+        ("upload[]", Some("unchecked.txt"), b"hello"),
+        ("upload[]", Some("after.txt"), b"hello"),
+    ]);
+    assert_eq!(reply.status, StatusCode::BadRequest);
+    assert!(server.exists("before.txt"));
+    assert!(!server.exists("unchecked.txt"));
+    assert!(server.exists("after.txt"));
+}
+
+#[test]
+fn upload_names_are_sanitized() {
+    let server = TestServer::start();
+
+    let reply = server.upload(&[
+        ("../escaped.txt", b"a"),
+        ("C:\\Users\\me\\win.txt", b"b"),
+        ("con.txt", b"c"),
+        ("bad\u{1}name?.txt", b"d"),
+    ]);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    let names: Vec<&str> = saved.iter().map(|s| s.saved_name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["escaped.txt", "win.txt", "_con.txt", "bad_name_.txt"]
+    );
+    assert_eq!(saved[0].source_name, "../escaped.txt");
+    assert!(server.exists("escaped.txt"));
+    assert!(!server
+        .dir
+        .path()
+        .parent()
+        .unwrap()
+        .join("escaped.txt")
+        .exists());
+}
+
+#[test]
+fn put_name_cannot_escape() {
+    let server = TestServer::start();
+
+    let reply = server.put("/files/..%2Fescaped.txt", Headers::new(), b"a");
+    assert_eq!(reply.status, StatusCode::Created);
+    let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.saved_name, "escaped.txt");
+    assert!(server.exists("escaped.txt"));
+}
+
+#[test]
+fn download_cannot_escape() {
+    let server = TestServer::start();
+    let outside = server.dir.path().parent().unwrap().join("na-outside.txt");
+    fs::write(&outside, b"secret").unwrap();
+
+    let reply = server.get("/files/..%2Fna-outside.txt");
+    fs::remove_file(&outside).unwrap();
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_policy() {
+    use na::directory::SymlinkPolicy;
+    use std::os::unix::fs::symlink;
+
+    let outside = tempdir::TempDir::new("na-outside").unwrap();
+    fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+    let setup = |server: &TestServer| {
+        server.write("a.txt", b"a");
+        symlink(server.path("a.txt"), server.path("inside")).unwrap();
+        symlink(outside.path().join("secret.txt"), server.path("outside")).unwrap();
+    };
+    let with_policy = |policy| {
+        let server = TestServer::start_in(|d| d.symlinks = policy);
+        setup(&server);
+        server
+    };
+
+    let server = with_policy(SymlinkPolicy::Within);
+    assert_eq!(server.get("/files/inside").status, StatusCode::Ok);
+    assert_eq!(server.get("/files/outside").status, StatusCode::NotFound);
+    assert!(!server.get("/list").text().contains("outside"));
+
+    let server = with_policy(SymlinkPolicy::Follow);
+    assert_eq!(server.get("/files/outside").body, b"secret".to_vec());
+
+    let server = with_policy(SymlinkPolicy::Never);
+    assert_eq!(server.get("/files/inside").status, StatusCode::NotFound);
+    assert_eq!(server.get("/list").text(), "/files/a.txt\n");
+}
+
+#[test]
+fn delete_moves_file_to_trash_and_restores_it() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("a.txt", b"precious");
+
+    let reply = server.delete("/files/a.txt");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(!server.exists("a.txt"));
+    assert!(!server.get("/list").text().contains("a.txt"));
+    let restore =
+        String::from_utf8(reply.headers.get_raw("Na-Restore").unwrap()[0].clone()).unwrap();
+
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+    let trash = server.request(Method::Get, "/trash", headers, b"").text();
+    assert!(trash.contains("\"name\":\"a.txt\""));
+
+    // The name was taken again in the meantime.
+    server.write("a.txt", b"new");
+    let reply = server.request(Method::Post, &restore, Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    let restored: na::trash::Restored = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(restored.restored_name, "a.txt (1)");
+    assert_eq!(server.read("a.txt (1)"), b"precious".to_vec());
+
+    let reply = server.request(Method::Post, &restore, Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[test]
+fn trash_purge() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("a.txt", b"a");
+    server.write("b.txt", b"b");
+
+    let reply = server.delete("/files/a.txt");
+    let restore =
+        String::from_utf8(reply.headers.get_raw("Na-Restore").unwrap()[0].clone()).unwrap();
+    let entry = restore.trim_end_matches("/restore").to_string();
+    assert_eq!(server.delete(&entry).status, StatusCode::Ok);
+    assert_eq!(server.delete(&entry).status, StatusCode::NotFound);
+
+    server.delete("/files/b.txt");
+    assert!(server.get("/trash").text().contains("b.txt"));
+    assert_eq!(server.delete("/trash").status, StatusCode::Ok);
+    assert!(!server.get("/trash").text().contains("b.txt"));
+}
+
+#[test]
+fn trash_name_is_reserved() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("a.txt", b"a");
+
+    let reply = server.upload(&[(".na-trash", b"x")]);
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved[0].saved_name, "_.na-trash");
+    let reply = server.put("/files/.na-trash", Headers::new(), b"x");
+    assert_eq!(reply.status, StatusCode::Created);
+    assert!(!server.path(".na-trash").is_file());
+
+    assert_eq!(server.delete("/files/a.txt").status, StatusCode::Ok);
+    assert!(server.path(".na-trash").is_dir());
+    assert!(!server.get("/list").text().contains("/files/.na-trash\n"));
+    assert_eq!(server.get("/files/.na-trash").status, StatusCode::NotFound);
+}
+
+#[test]
+fn hard_delete_skips_the_trash() {
+    let server = TestServer::with(|b| b.enable_delete(true).hard_delete(true));
+    server.write("a.txt", b"a");
+
+    let reply = server.delete("/files/a.txt");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(reply.headers.get_raw("Na-Restore").is_none());
+    assert_eq!(fs::read_dir(server.dir.path()).unwrap().count(), 0);
+    assert_eq!(server.get("/trash").status, StatusCode::NotFound);
+}
+
+#[test]
+fn trash_expires() {
+    let server = TestServer::with(|b| {
+        b.enable_delete(true)
+            .trash_retention(std::time::Duration::from_secs(0))
+    });
+    server.write("a.txt", b"a");
+
+    server.delete("/files/a.txt");
+    assert!(!server.get("/trash").text().contains("a.txt"));
+}
+
+#[test]
+fn trash_is_hidden_when_delete_is_disabled() {
+    let server = TestServer::start();
+
+    assert_eq!(server.get("/trash").status, StatusCode::NotFound);
+}
+
+fn versioned(keep: usize) -> TestServer {
+    TestServer::with(|b| {
+        b.versions(Some(na::versions::VersionPolicy {
+            keep: Some(keep),
+            max_age: None,
+        }))
+    })
+}
+
+#[test]
+fn versioned_upload_keeps_older_revisions() {
+    let server = versioned(10);
+
+    for contents in &["first", "second", "third"] {
+        let reply = server.put("/files/a.txt", Headers::new(), contents.as_bytes());
+        let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+        assert_eq!(saved.saved_name, "a.txt");
+    }
+    assert_eq!(server.get("/list").text(), "/files/a.txt\n");
+    assert_eq!(server.get("/files/a.txt").body, b"third".to_vec());
+    assert_eq!(server.get("/files/a.txt?version=1").body, b"first".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt?version=2").body,
+        b"second".to_vec()
+    );
+    assert_eq!(server.get("/files/a.txt?version=3").body, b"third".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt?version=4").status,
+        StatusCode::NotFound
+    );
+
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+    let revisions: Vec<na::versions::Revision> = json::decode(
+        &server
+            .request(Method::Get, "/versions/a.txt", headers, b"")
+            .text(),
+    )
+    .unwrap();
+    let numbers: Vec<u64> = revisions.iter().map(|r| r.version).collect();
+    assert_eq!(numbers, vec![3, 2, 1]);
+    assert!(revisions[0].current);
+}
+
+#[test]
+fn versions_name_is_reserved() {
+    let server = versioned(10);
+
+    let reply = server.put("/files/.na-versions", Headers::new(), b"x");
+    let saved: SavedFile = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved.saved_name, "_.na-versions");
+
+    server.put("/files/a.txt", Headers::new(), b"first");
+    server.put("/files/a.txt", Headers::new(), b"second");
+    assert!(server.path(".na-versions").is_dir());
+    assert_eq!(server.get("/files/a.txt?version=1").body, b"first".to_vec());
+    let list = server.get("/list").text();
+    assert_eq!(list.lines().count(), 2);
+    assert!(list.contains("/files/_.na-versions\n"));
+}
+
+#[test]
+fn versioned_restore() {
+    let server = versioned(10);
+    server.put("/files/a.txt", Headers::new(), b"first");
+    server.put("/files/a.txt", Headers::new(), b"second");
+
+    let reply = server.request(Method::Post, "/versions/a.txt/1", Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(server.read("a.txt"), b"first".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt?version=2").body,
+        b"second".to_vec()
+    );
+    assert_eq!(server.get("/files/a.txt?version=3").body, b"first".to_vec());
+}
+
+#[test]
+fn deleting_a_file_drops_its_versions() {
+    let server = TestServer::with(|b| {
+        b.enable_delete(true)
+            .versions(Some(na::versions::VersionPolicy {
+                keep: Some(10),
+                max_age: None,
+            }))
+    });
+    server.put("/files/a.txt", Headers::new(), b"first");
+    server.put("/files/a.txt", Headers::new(), b"second");
+    assert_eq!(server.delete("/files/a.txt").status, StatusCode::Ok);
+
+    server.put("/files/a.txt", Headers::new(), b"new");
+    assert_eq!(server.get("/files/a.txt?version=1").body, b"new".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt?version=2").status,
+        StatusCode::NotFound
+    );
+}
+
+#[test]
+fn version_links_are_encoded() {
+    let server = versioned(10);
+    server.put("/files/a%20b%23.txt", Headers::new(), b"first");
+    server.put("/files/a%20b%23.txt", Headers::new(), b"second");
+
+    let page = server.get("/versions/a%20b%23.txt").text();
+    assert!(page.contains("href=\"/files/a%20b%23.txt?version=1\""));
+    assert!(page.contains("res=\"/versions/a%20b%23.txt/1\""));
+}
+
+#[test]
+fn versions_are_pruned_by_count() {
+    let server = versioned(2);
+    for i in 0..5 {
+        server.put("/files/a.txt", Headers::new(), format!("{}", i).as_bytes());
+    }
+
+    assert_eq!(
+        server.get("/files/a.txt?version=1").status,
+        StatusCode::NotFound
+    );
+    assert_eq!(server.get("/files/a.txt?version=3").body, b"2".to_vec());
+    assert_eq!(server.get("/files/a.txt?version=4").body, b"3".to_vec());
+    assert_eq!(server.get("/files/a.txt?version=5").body, b"4".to_vec());
+}
+
+#[test]
+fn version_numbers_carry_on_after_pruning() {
+    let server = versioned(0);
+    for i in 0..3 {
+        server.put("/files/a.txt", Headers::new(), format!("{}", i).as_bytes());
+    }
+
+    assert_eq!(server.get("/files/a.txt?version=3").body, b"2".to_vec());
+    assert_eq!(
+        server.get("/files/a.txt?version=1").status,
+        StatusCode::NotFound
+    );
+}
+
+#[test]
+fn audit_log_only_names_users_that_gave_the_token() {
+    let logs = tempdir::TempDir::new("na-audit").unwrap();
+    let log = logs.path().join("audit.jsonl");
+    let path = log.clone();
+    let server = TestServer::with(move |b| b.audit_log(Some(path)));
+
+    let mut mallory = Headers::new();
+    mallory.set_raw("Authorization", vec![b"Basic bWFsbG9yeTp4".to_vec()]);
+    let reply = server.put("/files/a.txt", mallory, b"hello");
+    assert_eq!(reply.status, StatusCode::Created);
+
+    let events = na::audit::read(&log).unwrap();
+    assert_eq!(events[0].user, None);
+    assert!(events[0].remote_addr.starts_with("127.0.0.1:"));
+}
+
+#[test]
+fn audit_log_is_refused_inside_the_served_directory() {
+    let dir = tempdir::TempDir::new("na-audit").unwrap();
+    let result = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .audit_log(Some(dir.path().join("audit.jsonl")))
+        .start();
+    assert!(result.is_err());
+    assert!(!dir.path().join("audit.jsonl").exists());
+}
+
+#[test]
+fn audit_without_a_log_is_a_usage_error() {
+    assert!(na::audit::run("na", &[]).is_err());
+    assert!(na::audit::run("na", &["--help".to_string()]).is_ok());
+}
+
+#[test]
+fn audit_log_records_uploads_downloads_and_deletions() {
+    let logs = tempdir::TempDir::new("na-audit").unwrap();
+    let log = logs.path().join("audit.jsonl");
+    let path = log.clone();
+    let server = TestServer::with(move |b| {
+        b.token(Some("secret".to_string()))
+            .enable_delete(true)
+            .audit_log(Some(path))
+    });
+
+    let mut alice = Headers::new();
+    alice.set_raw("Authorization", vec![b"Basic YWxpY2U6c2VjcmV0".to_vec()]);
+    let reply = server.put("/files/con.txt", alice.clone(), b"hello");
+    assert_eq!(reply.status, StatusCode::Created);
+    let reply = server.request(Method::Get, "/files/_con.txt", alice.clone(), b"");
+    assert_eq!(reply.body, b"hello".to_vec());
+    let reply = server.request(Method::Delete, "/files/_con.txt", alice, b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+
+    let events = na::audit::read(&log).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+    assert_eq!(kinds, vec!["upload", "download", "delete"]);
+    assert_eq!(events[0].source_name, Some("con.txt".to_string()));
+    for e in &events {
+        assert_eq!(e.saved_name, "_con.txt");
+        assert_eq!(e.size, 5);
+        assert_eq!(e.user, Some("alice".to_string()));
+    }
+    assert_eq!(
+        events[0].sha256,
+        Some("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string())
+    );
+
+    let query = na::audit::Query {
+        since: None,
+        until: None,
+        user: Some("alice".to_string()),
+        file: Some("con.txt".to_string()),
+        event: None,
+    };
+    assert_eq!(events.iter().filter(|e| query.matches(e)).count(), 1);
+}
+
+#[test]
+fn audit_log_records_restores_and_purges() {
+    let logs = tempdir::TempDir::new("na-audit").unwrap();
+    let log = logs.path().join("audit.jsonl");
+    let path = log.clone();
+    let server = TestServer::with(move |b| {
+        b.enable_delete(true)
+            .versions(Some(na::versions::VersionPolicy {
+                keep: Some(10),
+                max_age: None,
+            }))
+            .audit_log(Some(path))
+    });
+
+    server.put("/files/a.txt", Headers::new(), b"first");
+    server.put("/files/a.txt", Headers::new(), b"second!");
+    let reply = server.request(Method::Post, "/versions/a.txt/1", Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    let reply = server.delete("/files/a.txt");
+    let restore =
+        String::from_utf8(reply.headers.get_raw("Na-Restore").unwrap()[0].clone()).unwrap();
+    let reply = server.request(Method::Post, &restore, Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    server.delete("/files/a.txt");
+    assert_eq!(server.delete("/trash").status, StatusCode::Ok);
+
+    let events = na::audit::read(&log).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+    assert_eq!(
+        kinds,
+        vec!["upload", "upload", "restore", "delete", "restore", "delete", "purge"]
+    );
+    for e in &events[2..] {
+        assert_eq!(e.saved_name, "a.txt");
+        assert_eq!(e.size, 5);
+    }
+}
+
+#[test]
+fn serves_every_listen_address() {
+    let dir = tempdir::TempDir::new("na-listen").unwrap();
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .also_bind("127.0.0.1:0")
+        .start()
+        .unwrap();
+    let addrs = handle.addrs();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[0] != addrs[1]);
+    for addr in &addrs {
+        let url = format!("http://{}/list", addr);
+        let mut res = na::hyper::Client::new().get(&*url).send().unwrap();
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "/files/a.txt\n");
+    }
+
+    // One address that can't be bound fails the whole start.
+    let taken = addrs[0].to_string();
+    let result = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .also_bind(&taken)
+        .start();
+    assert!(result.is_err());
+    handle.shutdown();
+}
+
+#[test]
+fn shutdown_frees_the_address() {
+    let dir = tempdir::TempDir::new("na-rebind").unwrap();
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .start()
+        .unwrap();
+    let addr = handle.addr();
+    let url = format!("http://{}/list", addr);
+    let res = na::hyper::Client::new().get(&*url).send().unwrap();
+    assert_eq!(res.status, StatusCode::Ok);
+    drop(res);
+
+    handle.shutdown();
+    assert!(TcpListener::bind(addr).is_ok());
+
+    // A start that fails halfway lets go of the addresses it did bind.
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let result = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind(&addr.to_string())
+        .also_bind(&taken.local_addr().unwrap().to_string())
+        .start();
+    assert!(result.is_err());
+    assert!(TcpListener::bind(addr).is_ok());
+}
+
+#[test]
+fn shutdown_from_a_hook_doesnt_wait_for_its_own_request() {
+    let dir = tempdir::TempDir::new("na-hook-shutdown").unwrap();
+    fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+    let running: Arc<Mutex<Option<na::ServerHandle>>> = Arc::new(Mutex::new(None));
+    let (done, stopped) = std::sync::mpsc::channel();
+    let done = Mutex::new(done);
+    let hook = running.clone();
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .on_download(move |_| {
+            if let Some(handle) = hook.lock().unwrap().take() {
+                handle.shutdown();
+                done.lock().unwrap().send(()).unwrap();
+            }
+        })
+        .start()
+        .unwrap();
+    let url = format!("http://{}/files/a.txt", handle.addr());
+    *running.lock().unwrap() = Some(handle);
+
+    let mut res = na::hyper::Client::new().get(&*url).send().unwrap();
+    let mut body = Vec::new();
+    res.read_to_end(&mut body).unwrap();
+    assert_eq!(body, b"hello".to_vec());
+    let stopped = stopped.recv_timeout(std::time::Duration::from_secs(10));
+    assert!(stopped.is_ok());
+}
+
+#[test]
+fn falls_back_to_another_port_when_taken() {
+    let dir = tempdir::TempDir::new("na-port").unwrap();
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken_addr = taken.local_addr().unwrap();
+
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind(&taken_addr.to_string())
+        .fallback_ports(vec![taken_addr.port(), 0])
+        .start()
+        .unwrap();
+    assert!(handle.addr().port() != taken_addr.port());
+
+    let startup: na::server::Startup = json::decode(&handle.startup_json(&[])).unwrap();
+    assert_eq!(startup.port, handle.addr().port());
+    assert_eq!(startup.addrs, vec![handle.addr().to_string()]);
+    handle.shutdown();
+}
+
+#[test]
+fn parses_port_ranges() {
+    assert_eq!(na::ip::parse_port("9000"), Ok((9000, vec![])));
+    assert_eq!(
+        na::ip::parse_port("9000-9002"),
+        Ok((9000, vec![9001, 9002]))
+    );
+    assert_eq!(na::ip::parse_port("auto"), Ok((8888, vec![0])));
+    assert!(na::ip::parse_port("9002-9000").is_err());
+    assert!(na::ip::parse_port("http").is_err());
+}
+
+#[test]
+fn cached_index_follows_changes() {
+    let server = TestServer::with(|b| {
+        b.enable_delete(true)
+            .index(Some(std::time::Duration::from_secs(3600)))
+    });
+    server.write("a.txt", b"a");
+    assert_eq!(server.get("/list").text(), "/files/a.txt\n");
+
+    // Changes na makes itself show up right away.
+    server.put("/files/b.txt", Headers::new(), b"b");
+    assert_eq!(server.get("/files/b.txt").body, b"b".to_vec());
+    server.delete("/files/a.txt");
+    assert_eq!(server.get("/list").text(), "/files/b.txt\n");
+    assert_eq!(server.get("/files/a.txt").status, StatusCode::NotFound);
+
+    // Others are noticed by watching the directory.
+    fs::remove_file(server.path("b.txt")).unwrap();
+    let mut listing = String::new();
+    for _ in 0..50 {
+        listing = server.get("/list").text();
+        if listing.is_empty() {
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(listing, "");
+}
+
+#[test]
+fn list_as_csv_and_xml() {
+    let server = TestServer::start();
+    server.write("a,b.txt", b"abc");
+
+    let reply = server.get("/list.csv");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(
+        reply.headers.get_raw("Content-Type").unwrap()[0],
+        b"text/csv; charset=utf-8".to_vec()
+    );
+    let csv = reply.text();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "name,url,size,modified");
+    assert!(lines[1].starts_with(&format!("\"a,b.txt\",{}/files/a%2Cb.txt,3,", server.base)));
+
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"text/csv".to_vec()]);
+    let reply = server.request(Method::Get, "/list", headers, b"");
+    assert_eq!(reply.text(), csv);
+
+    server.write("<x>.txt", b"x");
+    let reply = server.get("/list.xml");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert_eq!(
+        reply.headers.get_raw("Content-Type").unwrap()[0],
+        b"application/xml; charset=utf-8".to_vec()
+    );
+    let xml = reply.text();
+    assert!(xml.contains("name=\"&lt;x&gt;.txt\""));
+    assert!(xml.contains("size=\"1\""));
+}
+
+#[test]
+fn list_formats_take_the_token_in_the_query() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())));
+    server.write("a.txt", b"a");
+
+    for path in &[
+        "/list",
+        "/list.csv",
+        "/list.xml",
+        "/feed.atom",
+        "/playlist.m3u8",
+    ] {
+        let reply = server.get(&format!("{}?token=secret", path));
+        assert_eq!(reply.status, StatusCode::Ok, "{}", path);
+    }
+}
+
+#[test]
+fn browsers_keep_getting_the_plain_list() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+
+    let mut headers = Headers::new();
+    headers.set_raw(
+        "Accept",
+        vec![b"text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".to_vec()],
+    );
+    let reply = server.request(Method::Get, "/list", headers, b"");
+    assert_eq!(reply.text(), "/files/a.txt\n");
+}
+
+#[test]
+fn playlist_and_feed() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())));
+    server.write("song.mp3", b"la");
+    server.write("notes.txt", b"n");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Authorization", vec![b"Bearer secret".to_vec()]);
+    let reply = server.request(Method::Get, "/playlist.m3u8", headers.clone(), b"");
+    assert_eq!(
+        reply.text(),
+        format!(
+            "#EXTM3U\n#EXTINF:-1,song.mp3\n{}/files/song.mp3?token=secret\n",
+            server.base
+        )
+    );
+
+    let feed = server
+        .request(Method::Get, "/feed.atom", headers, b"")
+        .text();
+    assert!(feed.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert_eq!(feed.matches("<entry>").count(), 2);
+    assert!(feed.contains(&format!(
+        "<link href=\"{}/files/notes.txt?token=secret\" length=\"1\"/>",
+        server.base
+    )));
+}
+
+#[test]
+fn json_has_rfc3339_times() {
+    let server = TestServer::with(|b| b.utc(true));
+    server.write("a.txt", b"a");
+
+    let files: Vec<FileMeta> = json::decode(&server.get("/json").text()).unwrap();
+    let time = &files[0].modified_rfc3339;
+    assert_eq!(time.len(), "2016-08-02T02:08:53Z".len());
+    assert!(time.ends_with('Z'));
+    assert!(files[0].modified.ends_with(" UTC"));
+}
+
+#[test]
+fn page_uses_units_and_language() {
+    let server = TestServer::with(|b| b.units(Units::Iec));
+    server.write("a.bin", &[0; 1536]);
+
+    let mut headers = Headers::new();
+    headers.set_raw(
+        "Accept-Language",
+        vec![b"fr;q=0.5, de-AT, en;q=0.8".to_vec()],
+    );
+    let page = server.request(Method::Get, "/", headers, b"").text();
+    assert!(page.contains("<html lang=\"de\">"));
+    assert!(page.contains("1.5 KiB"));
+
+    let page = server.get("/").text();
+    assert!(page.contains("<html lang=\"en\">"));
+
+    // Croatian isn't Serbian.
+    let mut headers = Headers::new();
+    headers.set_raw("Accept-Language", vec![b"hr".to_vec()]);
+    let page = server.request(Method::Get, "/", headers, b"").text();
+    assert!(page.contains("<html lang=\"en\">"));
+}
+
+#[test]
+fn size_units() {
+    assert_eq!(locale::size(999, Units::Si), "999 B");
+    assert_eq!(locale::size(1536, Units::Si), "1.5 kB");
+    assert_eq!(locale::size(999_960, Units::Si), "1.0 MB");
+    assert_eq!(locale::size(1536, Units::Iec), "1.5 KiB");
+    assert_eq!(locale::size(1024 * 1024, Units::Iec), "1.0 MiB");
+}
+
+#[test]
+fn index_page_for_terminals() {
+    let server = TestServer::start();
+    server.write("a.txt", b"a");
+    server.write("longer name.bin", &[0; 2000]);
+
+    let mut headers = Headers::new();
+    headers.set_raw("User-Agent", vec![b"curl/8.5.0".to_vec()]);
+    headers.set_raw("Accept", vec![b"*/*".to_vec()]);
+    let reply = server.request(Method::Get, "/", headers, b"");
+    assert_eq!(
+        reply.headers.get_raw("Content-Type").unwrap()[0],
+        b"text/plain; charset=utf-8".to_vec()
+    );
+    let table = reply.text();
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with(&format!("NAME{}SIZE  MODIFIED", " ".repeat(15))));
+    assert!(lines[1].starts_with(&format!("a.txt{}1 B  ", " ".repeat(15))));
+    assert!(lines[1].ends_with(&format!("  {}/files/a.txt", server.base)));
+    assert!(lines[2].starts_with("longer name.bin  2.0 kB  "));
+
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+    let reply = server.request(Method::Get, "/", headers, b"");
+    let files: Vec<FileMeta> = json::decode(&reply.text()).unwrap();
+    assert_eq!(files.len(), 2);
+
+    assert!(server.get("/").text().contains("<html"));
+}
+
+#[test]
+fn help_page() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())).enable_delete(true));
+    server.write("a.txt", b"a");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Authorization", vec![b"Bearer secret".to_vec()]);
+    let help = server.request(Method::Get, "/help", headers, b"").text();
+    let curl = "curl -H 'Authorization: Bearer secret'";
+    assert!(help.contains(&format!("{} -o 'a.txt' {}/files/a.txt", curl, server.base)));
+    assert!(help.contains(&format!("{} -F upload=@example.txt {}/", curl, server.base)));
+    assert!(help.contains(&format!("{} -X DELETE {}/files/a.txt", curl, server.base)));
+}
+
+fn accept_json() -> Headers {
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+    headers
+}
+
+#[test]
+fn api_errors_are_json() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())));
+
+    let reply = server.request(Method::Get, "/files/missing.txt", accept_json(), b"");
+    assert_eq!(reply.status, StatusCode::Unauthorized);
+    let error: ErrorReply = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(error.error.status, 401);
+    assert_eq!(error.error.code, "unauthorized");
+
+    let mut headers = accept_json();
+    headers.set_raw("Authorization", vec![b"Bearer secret".to_vec()]);
+    let reply = server.request(Method::Get, "/files/missing.txt", headers, b"");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    let error: ErrorReply = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(error.error.code, "not_found");
+
+    let reply = server.request(Method::Get, "/files/missing.txt", Headers::new(), b"");
+    assert!(reply.text().starts_with("Unauthorized (401). "));
+}
+
+#[test]
+fn api_uploads_are_created_and_deletes_have_no_content() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+
+    let reply = server.post_form_with(accept_json(), &[("upload[]", Some("a.txt"), b"a")]);
+    assert_eq!(reply.status, StatusCode::Created);
+    assert_eq!(
+        reply.headers.get_raw("Location").unwrap()[0],
+        b"/files/a.txt".to_vec()
+    );
+    let saved: Vec<SavedFile> = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(saved[0].saved_name, "a.txt");
+
+    let reply = server.request(Method::Delete, "/files/a.txt", accept_json(), b"");
+    assert_eq!(reply.status, StatusCode::NoContent);
+    assert_eq!(reply.text(), "");
+    assert!(!server.exists("a.txt"));
+}
+
+#[test]
+fn api_upload_reports_partial_failures() {
+    let server = TestServer::start();
+    let digest = format!("sha-256={}", HELLO_SHA256_HEX);
+
+    let reply = server.post_form_with(
+        accept_json(),
+        &[
+            ("upload[]", Some("good.txt"), b"good"),
+            ("digest", None, digest.as_bytes()),
+            ("upload[]", Some("bad.txt"), b"corrupted"),
+        ],
+    );
+    assert_eq!(reply.status, StatusCode::MultiStatus);
+    let report: UploadReport = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(report.saved.len(), 1);
+    assert_eq!(report.saved[0].saved_name, "good.txt");
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].status, 400);
+    assert_eq!(report.failed[0].code, "digest_mismatch");
+    assert_eq!(report.failed[0].file, Some("bad.txt".to_string()));
+    assert!(server.exists("good.txt"));
+    assert!(!server.exists("bad.txt"));
+
+    let reply = server.post_form_with(
+        accept_json(),
+        &[
+            ("digest", None, digest.as_bytes()),
+            ("upload[]", Some("bad.txt"), b"corrupted"),
+        ],
+    );
+    assert_eq!(reply.status, StatusCode::BadRequest);
+    let error: ErrorReply = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(error.error.code, "digest_mismatch");
+
+    // So is a digest field that doesn't parse.
+    let reply = server.post_form_with(
+        accept_json(),
+        &[
+            ("upload[]", Some("first.txt"), b"first"),
+            ("digest", None, b"sha-256=nonsense"),
+            ("upload[]", Some("second.txt"), b"second"),
+        ],
+    );
+    assert_eq!(reply.status, StatusCode::MultiStatus);
+    let report: UploadReport = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(report.saved.len(), 1);
+    assert_eq!(report.saved[0].saved_name, "first.txt");
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].code, "bad_request");
+    assert_eq!(report.failed[0].file, Some("second.txt".to_string()));
+}
+
+#[test]
+fn api_v1_files() {
+    let server = TestServer::with(|b| b.enable_delete(true));
+    server.write("b.txt", b"bb");
+    server.write("a.txt", b"a");
+
+    let files: Vec<api::File> = json::decode(&server.get("/api/v1/files").text()).unwrap();
+    let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["a.txt", "b.txt"]);
+    assert_eq!(files[1].size, 2);
+    assert_eq!(files[1].content_url, "/api/v1/files/b.txt/content");
+    assert!(files[1].modified.ends_with('Z'));
+
+    let file: api::File = json::decode(&server.get("/api/v1/files/b.txt").text()).unwrap();
+    assert_eq!(file.name, "b.txt");
+    assert_eq!(file.content_type, "text/plain; charset=utf-8");
+    assert_eq!(server.get(&file.content_url).text(), "bb");
+
+    let reply = server.get("/api/v1/files/missing.txt");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    let error: ErrorReply = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(error.error.code, "not_found");
+
+    let reply = server.put("/api/v1/files/c.txt", Headers::new(), b"ccc");
+    assert_eq!(reply.status, StatusCode::Created);
+    assert_eq!(server.read("c.txt"), b"ccc".to_vec());
+
+    let reply = server.request(Method::Delete, "/api/v1/files/c.txt", Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::NoContent);
+    assert!(!server.exists("c.txt"));
+}
+
+#[test]
+fn api_v1_upload_and_info() {
+    let server = TestServer::start();
+
+    let reply = server.request(Method::Get, "/api/v1/info", Headers::new(), b"");
+    let info: api::Info = json::decode(&reply.text()).unwrap();
+    assert_eq!(info.version, na::VERSION);
+    assert_eq!(info.api_version, 1);
+    assert!(info.upload);
+    assert!(!info.delete);
+    assert!(!info.overwrite);
+
+    let spec = Json::from_str(&server.get("/api/v1/openapi.json").text()).unwrap();
+    assert_eq!(spec["info"]["version"].as_string(), Some(na::VERSION));
+    assert!(spec["paths"].find("/files/{name}/content").is_some());
+}
+
+#[test]
+fn api_v1_info_reports_whether_uploads_replace_files() {
+    let overwrites = |server: &TestServer| -> bool {
+        let info: api::Info = json::decode(&server.get("/api/v1/info").text()).unwrap();
+        info.overwrite
+    };
+    assert!(overwrites(&TestServer::with(|b| b.overwrite(true))));
+    assert!(!overwrites(&TestServer::with(|b| b
+        .overwrite(true)
+        .dropbox(true))));
+    assert!(overwrites(&versioned(10)));
+}
+
+#[test]
+fn api_v1_info_reports_the_trash_only_where_it_can_be_browsed() {
+    let info = |server: &TestServer| -> api::Info {
+        json::decode(&server.get("/api/v1/info").text()).unwrap()
+    };
+    let server = TestServer::with(|b| b.enable_delete(true));
+    assert!(info(&server).trash);
+    assert_eq!(server.get("/trash").status, StatusCode::Ok);
+
+    let server = TestServer::with(|b| b.enable_delete(true).dropbox(true));
+    assert!(!info(&server).trash);
+    assert_eq!(server.get("/trash").status, StatusCode::NotFound);
+
+    // Nobody gets to see, or take back, what someone else deleted.
+    let session = server.put("/files/a.txt", Headers::new(), b"a").session();
+    let reply = server.request(Method::Delete, "/files/a.txt", session, b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+    assert!(reply.headers.get_raw("Na-Restore").is_none());
+    let id = fs::read_dir(server.path(".na-trash"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .next()
+        .unwrap();
+    let restore = format!("/trash/{}/restore", id);
+    let reply = server.request(Method::Post, &restore, Headers::new(), b"");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    assert!(!server.exists("a.txt"));
+    let reply = server.delete(&format!("/trash/{}", id));
+    assert_eq!(reply.status, StatusCode::NotFound);
+}
+
+#[test]
+fn get_downloads_matching_files_and_resumes() {
+    let server = TestServer::with(|b| b.token(Some("secret".to_string())));
+    server.write("a.txt", b"first file");
+    server.write("notes.txt", b"second file");
+    server.write("b.md", b"# not this one");
+
+    let dir = tempdir::TempDir::new("na-get").unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir_all(&out).unwrap();
+    // Left behind by an earlier, interrupted run.
+    fs::write(out.join("notes.txt"), b"second").unwrap();
+    // Another version of the file, which mustn't be added to.
+    fs::write(out.join("a.txt"), b"old").unwrap();
+    let args = vec![
+        "-t".to_string(),
+        "secret".to_string(),
+        "-j".to_string(),
+        "2".to_string(),
+        "--resume".to_string(),
+        "--verify".to_string(),
+        "-o".to_string(),
+        out.to_str().unwrap().to_string(),
+        server.base.clone(),
+        "*.txt".to_string(),
+    ];
+    client::run("na", "get", &args).unwrap();
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"first file".to_vec());
+    assert_eq!(
+        fs::read(out.join("notes.txt")).unwrap(),
+        b"second file".to_vec()
+    );
+    assert!(!out.join("b.md").exists());
+
+    // Nor is one of the same size. The token can also come with the
+    // address na prints.
+    fs::write(out.join("a.txt"), b"FIRST FILE").unwrap();
+    let args = vec![
+        "--resume".to_string(),
+        "-o".to_string(),
+        out.to_str().unwrap().to_string(),
+        format!("{}/?token=secret", server.base),
+        "a.txt".to_string(),
+    ];
+    client::run("na", "get", &args).unwrap();
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"first file".to_vec());
+
+    let args = vec![server.base.clone(), "*.txt".to_string()];
+    assert!(client::run("na", "get", &args).is_err());
+}
+
+#[test]
+fn get_keeps_hostile_names_inside_the_output_directory() {
+    // Stands in for a server that lists a file outside of where it's
+    // saved to.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).to_string();
+            let body = if request.starts_with("GET /json ") {
+                "[{\"name\":\"../escaped.txt\",\"url\":\"/files/escaped.txt\",\"size\":3,\
+                 \"modified\":\"\",\"modified_raw\":0,\"modified_rfc3339\":\"\"}]"
+            } else {
+                "abc"
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+
+    let dir = tempdir::TempDir::new("na-get").unwrap();
+    let out = dir.path().join("out");
+    let args = vec![
+        "--no-resume".to_string(),
+        "-o".to_string(),
+        out.to_str().unwrap().to_string(),
+        url,
+    ];
+    client::run("na", "get", &args).unwrap();
+    assert_eq!(fs::read(out.join("escaped.txt")).unwrap(), b"abc".to_vec());
+    assert!(!dir.path().join("escaped.txt").exists());
+}
+
+#[test]
+fn snippets_take_an_expiry_in_the_query() {
+    let server = TestServer::start();
+
+    let reply = server.request(
+        Method::Post,
+        "/snippets?expires=1h",
+        Headers::new(),
+        b"hello",
+    );
+    assert_eq!(reply.status, StatusCode::Created);
+    let snippet = Json::from_str(&reply.text()).unwrap();
+    let created = snippet["created"].as_u64().unwrap();
+    assert_eq!(snippet["expires"].as_u64(), Some(created + 3600));
+
+    let reply = server.request(
+        Method::Post,
+        "/snippets?expires=soon",
+        Headers::new(),
+        b"hello",
+    );
+    assert_eq!(reply.status, StatusCode::BadRequest);
+
+    let list = Json::from_str(&server.get("/snippets").text()).unwrap();
+    assert_eq!(list.as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn snippets_larger_than_a_mebibyte_are_refused() {
+    let server = TestServer::start();
+
+    let reply = server.request(
+        Method::Post,
+        "/snippets",
+        accept_json(),
+        &vec![b'a'; 1024 * 1024 + 1],
+    );
+    assert_eq!(reply.status, StatusCode::PayloadTooLarge);
+    let error: ErrorReply = json::decode(reply.text().trim()).unwrap();
+    assert_eq!(error.error.code, "too_large");
+    assert_eq!(server.get("/snippets").text(), "[]");
+
+    let reply = server.request(
+        Method::Post,
+        "/snippets",
+        Headers::new(),
+        &vec![b'a'; 1024 * 1024],
+    );
+    assert_eq!(reply.status, StatusCode::Created);
+}
+
+#[test]
+fn drop_box_has_no_snippets() {
+    let server = TestServer::with(|b| b.dropbox(true).enable_delete(true));
+    assert!(!server.get("/").text().contains("snippet-form"));
+
+    let reply = server.request(Method::Post, "/snippets", Headers::new(), b"hello");
+    assert_eq!(reply.status, StatusCode::MethodNotAllowed);
+    server.post_form(&[("snippet", None, b"hello")]);
+    assert_eq!(server.get("/snippets/1").status, StatusCode::NotFound);
+    assert_eq!(server.get("/snippets").text(), "[]");
+    assert_eq!(
+        server.delete("/snippets/1").status,
+        StatusCode::MethodNotAllowed
+    );
+}
+
+#[test]
+fn drop_box_hides_uploads_from_other_sessions() {
+    let server = TestServer::with(|b| b.dropbox(true).enable_delete(true));
+    let alice = server
+        .put("/files/a.txt", Headers::new(), b"alice's")
+        .session();
+    let bob = server.get("/").session();
+    let as_bob = |method: Method, path: &str| server.request(method, path, bob.clone(), b"");
+
+    assert!(!as_bob(Method::Get, "/").text().contains("a.txt"));
+    assert_eq!(as_bob(Method::Get, "/api/v1/files").text(), "[]");
+    assert_eq!(
+        as_bob(Method::Get, "/files/a.txt").status,
+        StatusCode::NotFound
+    );
+    assert_eq!(
+        as_bob(Method::Delete, "/files/a.txt").status,
+        StatusCode::NotFound
+    );
+    assert!(server.exists("a.txt"));
+
+    let reply = server.request(Method::Get, "/files/a.txt", alice, b"");
+    assert_eq!(reply.body, b"alice's".to_vec());
+}
+
+#[test]
+fn drop_box_forgets_deleted_files() {
+    let server = TestServer::with(|b| b.dropbox(true).enable_delete(true));
+    let alice = server
+        .put("/files/a.txt", Headers::new(), b"alice's")
+        .session();
+    let reply = server.request(Method::Delete, "/files/a.txt", alice.clone(), b"");
+    assert_eq!(reply.status, StatusCode::Ok);
+
+    let bob = server
+        .put("/files/a.txt", Headers::new(), b"bob's")
+        .session();
+    assert_eq!(server.read("a.txt"), b"bob's".to_vec());
+    let reply = server.request(Method::Get, "/files/a.txt", alice.clone(), b"");
+    assert_eq!(reply.status, StatusCode::NotFound);
+    let reply = server.request(Method::Get, "/api/v1/files", alice, b"");
+    assert_eq!(reply.text(), "[]");
+
+    let reply = server.request(Method::Get, "/files/a.txt", bob, b"");
+    assert_eq!(reply.body, b"bob's".to_vec());
+}
+
+#[test]
+fn trash_and_versions_are_shown_like_the_list() {
+    let server = TestServer::with(|b| {
+        b.enable_delete(true)
+            .utc(true)
+            .units(Units::Iec)
+            .versions(Some(na::versions::VersionPolicy {
+                keep: Some(10),
+                max_age: None,
+            }))
+    });
+    let big = vec![b'x'; 2048];
+    server.put("/files/a.txt", Headers::new(), &big);
+    server.put("/files/a.txt", Headers::new(), b"b");
+    server.write("b.txt", &big);
+    server.delete("/files/b.txt");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Accept", vec![b"application/json".to_vec()]);
+    let revisions: Vec<na::versions::Revision> = json::decode(
+        &server
+            .request(Method::Get, "/versions/a.txt", headers.clone(), b"")
+            .text(),
+    )
+    .unwrap();
+    assert!(revisions.iter().all(|r| r.modified.ends_with(" UTC")));
+    let trash: Vec<na::trash::TrashEntry> =
+        json::decode(&server.request(Method::Get, "/trash", headers, b"").text()).unwrap();
+    assert!(trash[0].deleted.ends_with(" UTC"));
+
+    assert!(server.get("/versions/a.txt").text().contains("2.0 KiB"));
+    assert!(server.get("/trash").text().contains("2.0 KiB"));
+}
+
+#[test]
+fn discovers_an_announced_instance() {
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let listening =
+        thread::spawn(move || na::discovery::listen(port, std::time::Duration::from_secs(3)));
+    thread::sleep(std::time::Duration::from_millis(200));
+
+    let beacon = na::discovery::Beacon::new(
+        "test".to_string(),
+        "http://127.0.0.1:9999/".to_string(),
+        na::VERSION,
+        vec!["upload".to_string()],
+    );
+    na::discovery::announce(beacon, port).unwrap();
+
+    let found = listening.join().unwrap().unwrap();
+    let &(_, ref heard) = found
+        .iter()
+        .find(|&&(_, ref b)| b.url == "http://127.0.0.1:9999/")
+        .unwrap();
+    assert_eq!(heard.name, "test");
+    assert_eq!(heard.capabilities, vec!["upload".to_string()]);
+}
+
+#[test]
+fn stops_at_the_download_limit_with_a_summary() {
+    let dir = tempdir::TempDir::new("na-shutdown").unwrap();
+    fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .max_downloads(Some(1))
+        .start()
+        .unwrap();
+    let state = handle.state().clone();
+    let base = format!("http://{}", handle.addr());
+
+    let client = na::hyper::Client::new();
+    let url = format!("{}/files/b.txt", base);
+    let res = client.put(&*url).body("abc").send().unwrap();
+    assert_eq!(res.status, StatusCode::Created);
+    drop(res);
+    let url = format!("{}/files/a.txt", base);
+    let mut res = client.get(&*url).send().unwrap();
+    let mut body = Vec::new();
+    res.read_to_end(&mut body).unwrap();
+    assert_eq!(body, b"hello".to_vec());
+    let res = client.get(&*url).send().unwrap();
+    assert_eq!(res.status, StatusCode::ServiceUnavailable);
+    drop(res);
+
+    let reason = handle.run_until(&na::shutdown::ShutdownPolicy {
+        deadline: None,
+        idle: None,
+        max_downloads: Some(1),
+    });
+    assert_eq!(reason.to_string(), "download limit reached");
+    let summary = state.activity.summary();
+    assert!(summary.starts_with("Served 3 requests in "));
+    assert!(summary.ends_with(": 1 downloads (5 bytes sent), 1 uploads (3 bytes received)"));
+}
+
+#[test]
+fn stops_when_idle() {
+    let dir = tempdir::TempDir::new("na-shutdown").unwrap();
+    let handle = na::ServerBuilder::new(na::Directory::new(dir.path().to_path_buf()))
+        .bind("127.0.0.1:0")
+        .start()
+        .unwrap();
+    let state = handle.state().clone();
+
+    let reason = handle.run_until(&na::shutdown::ShutdownPolicy {
+        deadline: Some(std::time::Duration::from_secs(60)),
+        idle: Some(std::time::Duration::from_secs(1)),
+        max_downloads: None,
+    });
+    assert_eq!(reason.to_string(), "idle for too long");
+    assert!(state
+        .activity
+        .summary()
+        .starts_with("Served 0 requests in "));
+}
+
+#[test]
+fn parses_durations() {
+    use na::shutdown::{parse_duration, parse_timeout};
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("0"), Ok(Duration::from_secs(0)));
+    assert!(parse_duration("1x").is_err());
+    assert!(parse_duration("18446744073709551615d").is_err());
+    assert!(parse_duration("18446744073709551615s1").is_err());
+    assert_eq!(parse_timeout("2m"), Ok(Duration::from_secs(120)));
+    assert!(parse_timeout("0s").is_err());
+}
+
+#[test]
+fn shared_files_cant_be_deleted() {
+    let served = tempdir::TempDir::new("na-shared").unwrap();
+    let elsewhere = tempdir::TempDir::new("na-shared").unwrap();
+    let file = elsewhere.path().join("a.txt");
+    fs::write(&file, b"mine").unwrap();
+    let mut directory = na::Directory::new(served.path().to_path_buf());
+    directory.files = Some(vec![file.clone()]);
+    let handle = na::ServerBuilder::new(directory)
+        .bind("127.0.0.1:0")
+        .enable_delete(true)
+        .start()
+        .unwrap();
+
+    let url = format!("http://{}/files/a.txt", handle.addr());
+    let res = na::hyper::Client::new().delete(&*url).send().unwrap();
+    assert_eq!(res.status, StatusCode::MethodNotAllowed);
+    drop(res);
+    assert_eq!(fs::read(&file).unwrap(), b"mine".to_vec());
+    assert!(!served.path().join(".na-trash").exists());
+    handle.shutdown();
+}
+
+#[test]
+fn streams_standard_input_to_the_first_downloader() {
+    let dir = tempdir::TempDir::new("na-stream").unwrap();
+    let mut directory = na::Directory::new(dir.path().to_path_buf());
+    directory.stream = Some(na::directory::Stream::from_reader(
+        "stdin.txt".to_string(),
+        std::io::Cursor::new(b"streamed".to_vec()),
+    ));
+    let handle = na::ServerBuilder::new(directory)
+        .bind("127.0.0.1:0")
+        .start()
+        .unwrap();
+    let base = format!("http://{}", handle.addr());
+    let get = |path: &str| {
+        let url = format!("{}{}", base, path);
+        let mut res = na::hyper::Client::new().get(&*url).send().unwrap();
+        let mut body = String::new();
+        res.read_to_string(&mut body).unwrap();
+        (res.status, body)
+    };
+
+    assert_eq!(get("/list").1, "/files/stdin.txt\n");
+    assert_eq!(
+        get("/files/stdin.txt"),
+        (StatusCode::Ok, "streamed".to_string())
+    );
+    assert_eq!(get("/files/stdin.txt").0, StatusCode::NotFound);
+    assert_eq!(get("/list").1, "");
+    handle.shutdown();
+}